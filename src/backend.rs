@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::clock::Clock;
+use crate::error::Error;
+use crate::filter::ChannelFilter;
+use crate::irc::{self, IrcWriter, Status};
+use crate::notification::Notification;
+use crate::stats::DeliveryCounters;
+use crate::webhook::WebhookWriter;
+
+/// Where messages go unless `service.backends` says otherwise.
+const DEFAULT_BACKENDS: &[&str] = &["irc"];
+
+/// Names of the backends to send messages to, read from `service.backends`.
+pub fn backends(cfg: &config::Config) -> Result<Vec<String>, Error> {
+    match cfg.get::<Vec<String>>("service.backends") {
+        Ok(backends) => Ok(backends),
+        Err(config::ConfigError::NotFound(_)) => {
+            Ok(DEFAULT_BACKENDS.iter().map(|b| String::from(*b)).collect())
+        }
+        Err(e) => Err(Error::Config(format!(
+            "failed to parse service.backends: {}",
+            e
+        ))),
+    }
+}
+
+/// Filters of the backends that have them, read from `filters.backends`.
+pub fn filters(cfg: &config::Config) -> Result<HashMap<String, ChannelFilter>, Error> {
+    match cfg.get::<HashMap<String, ChannelFilter>>("filters.backends") {
+        Ok(filters) => Ok(filters),
+        Err(config::ConfigError::NotFound(_)) => Ok(HashMap::new()),
+        Err(e) => Err(Error::Config(format!(
+            "failed to parse filters.backends: {}",
+            e
+        ))),
+    }
+}
+
+/// Prefixes for the messages of each project, like `[api]`, by the path
+/// or name of the project, read from the `projects` table.
+pub fn project_prefixes(cfg: &config::Config) -> Result<HashMap<String, String>, Error> {
+    match cfg.get::<HashMap<String, String>>("projects") {
+        Ok(prefixes) => Ok(prefixes),
+        Err(config::ConfigError::NotFound(_)) => Ok(HashMap::new()),
+        Err(e) => Err(Error::Config(format!("failed to parse projects: {}", e))),
+    }
+}
+
+/// Puts the prefix of their project in front of every line of notifications.
+pub struct Tagged {
+    writer: Box<IrcWriter + Send>,
+    prefixes: HashMap<String, String>,
+}
+
+impl Tagged {
+    /// The prefix for `project`, by its path or otherwise its name.
+    fn prefix(&self, project: &str) -> Option<&String> {
+        // keys are lowercased when the configuration is read
+        let project = project.to_lowercase();
+        let name = project.rsplit('/').next().unwrap_or_default();
+        self.prefixes
+            .get(&project)
+            .or_else(|| self.prefixes.get(name))
+    }
+}
+
+impl IrcWriter for Tagged {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        self.writer.write(message)
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        let prefix = match notification.project {
+            Some(ref project) => self.prefix(project).cloned(),
+            None => None,
+        };
+        match prefix {
+            Some(prefix) => {
+                let message = notification
+                    .message
+                    .lines()
+                    .map(|l| format!("{} {}", prefix, l))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.writer.notify(&Notification {
+                    message,
+                    ..notification.clone()
+                })
+            }
+            None => self.writer.notify(notification),
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.writer.reconnect()
+    }
+
+    fn status(&self) -> Status {
+        self.writer.status()
+    }
+
+    fn deliveries(&self) -> DeliveryCounters {
+        self.writer.deliveries()
+    }
+}
+
+/// Only passes on the notifications its filter accepts to a backend.
+pub struct Filtered {
+    writer: Box<IrcWriter + Send>,
+    filter: ChannelFilter,
+}
+
+impl IrcWriter for Filtered {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        self.writer.write(message)
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        if self.filter.accepts(notification) {
+            self.writer.notify(notification)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.writer.reconnect()
+    }
+
+    fn status(&self) -> Status {
+        self.writer.status()
+    }
+
+    fn deliveries(&self) -> DeliveryCounters {
+        self.writer.deliveries()
+    }
+}
+
+/// Prints every message on its own line, handy for trying out a configuration.
+pub struct StdoutWriter<W> {
+    out: W,
+}
+
+impl<W: Write> IrcWriter for StdoutWriter<W> {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        writeln!(self.out, "{}", message)
+            .and_then(|_| self.out.flush())
+            .map_err(|e| Error::Backend(format!("failed to write to stdout: {}", e)))
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            connected: true,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// The `stdout` backend.
+pub fn stdout() -> Box<IrcWriter + Send> {
+    Box::new(StdoutWriter { out: io::stdout() })
+}
+
+/// Sends everything to all of the configured backends.
+pub struct Backends {
+    writers: Vec<Box<IrcWriter + Send>>,
+}
+
+impl IrcWriter for Backends {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.write(message).err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        self.result(errors)
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.notify(notification).err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        self.result(errors)
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.reconnect().err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        self.result(errors)
+    }
+
+    fn status(&self) -> Status {
+        self.writers.iter().map(|w| w.status()).fold(
+            Status {
+                connected: true,
+                channels: Vec::new(),
+            },
+            |mut acc, s| {
+                acc.connected &= s.connected;
+                acc.channels.extend(s.channels);
+                acc
+            },
+        )
+    }
+
+    fn deliveries(&self) -> DeliveryCounters {
+        self.writers
+            .iter()
+            .fold(DeliveryCounters::default(), |mut acc, w| {
+                acc.merge(w.deliveries());
+                acc
+            })
+    }
+}
+
+impl Backends {
+    fn result(&self, errors: Vec<String>) -> Result<(), Error> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Backend(errors.join(", ")))
+        }
+    }
+}
+
+/// Sets up the configured backends, only connecting
+/// to IRC when it is one of them.
+pub fn init(
+    cfg: &config::Config,
+    clock: Arc<Clock + Send + Sync>,
+    logger: &slog::Logger,
+) -> Result<Box<IrcWriter + Send>, Error> {
+    let backends = backends(cfg)?;
+    let mut filters = filters(cfg)?;
+    let prefixes = project_prefixes(cfg)?;
+    if let Some(unknown) = filters.keys().find(|f| !backends.contains(f)) {
+        return Err(Error::Config(format!(
+            "filters.backends has filters for {}, which is not one of the backends",
+            unknown
+        )));
+    }
+
+    let mut writers = backends
+        .iter()
+        .map(|b| -> Result<Box<IrcWriter + Send>, Error> {
+            let writer: Box<IrcWriter + Send> = match b.as_str() {
+                "irc" => {
+                    info!(logger, "connecting to IRC");
+                    irc::init(cfg, clock.clone(), logger)?
+                }
+                "stdout" => stdout(),
+                "webhook" => Box::new(WebhookWriter::from_config(cfg, clock.clone(), logger)?),
+                other => return Err(Error::Config(format!("unknown backend {}", other))),
+            };
+            Ok(match filters.remove(b) {
+                Some(filter) => Box::new(Filtered { writer, filter }),
+                None => writer,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let writer: Box<IrcWriter + Send> = match writers.len() {
+        0 => return Err(Error::Config(String::from("no backends configured"))),
+        1 => writers.remove(0),
+        _ => Box::new(Backends { writers }),
+    };
+    if prefixes.is_empty() {
+        Ok(writer)
+    } else {
+        Ok(Box::new(Tagged { writer, prefixes }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use std::sync::Mutex;
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    #[test]
+    fn default_backends() {
+        let cfg = config::Config::default();
+        assert_eq!(backends(&cfg).unwrap(), vec![String::from("irc")]);
+    }
+
+    #[test]
+    fn stdout_without_irc() {
+        let mut cfg = config::Config::default();
+        cfg.set("service.backends", vec!["stdout"]).unwrap();
+        // the IRC settings do not matter without the irc backend
+        cfg.set("irc.channels", Vec::<String>::new()).unwrap();
+
+        let mut writer = init(&cfg, Arc::new(SystemClock), &logger()).unwrap();
+        assert!(writer.write("hello").is_ok());
+        assert!(writer.status().connected);
+    }
+
+    #[test]
+    fn irc_without_config() {
+        let cfg = config::Config::default();
+        assert!(init(&cfg, Arc::new(SystemClock), &logger()).is_err());
+    }
+
+    #[test]
+    fn unknown_backend() {
+        let mut cfg = config::Config::default();
+        cfg.set("service.backends", vec!["carrier-pigeon"]).unwrap();
+
+        match init(&cfg, Arc::new(SystemClock), &logger()) {
+            Err(Error::Config(e)) => assert_eq!(e, "unknown backend carrier-pigeon"),
+            _ => panic!("expected a config error"),
+        }
+    }
+
+    /// Keeps the messages of the notifications it gets.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl IrcWriter for Recorder {
+        fn write(&mut self, message: &str) -> Result<(), Error> {
+            self.0.lock().unwrap().push(message.to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn backend_filters() {
+        let everything = Recorder::default();
+        let failures = Recorder::default();
+        let mut writer = Backends {
+            writers: vec![
+                Box::new(everything.clone()),
+                Box::new(Filtered {
+                    writer: Box::new(failures.clone()),
+                    filter: ChannelFilter {
+                        statuses: vec![String::from("failed")],
+                        ..ChannelFilter::default()
+                    },
+                }),
+            ],
+        };
+
+        for status in &["success", "failed"] {
+            let pipeline = Notification {
+                kind: String::from("pipeline"),
+                status: Some(String::from(*status)),
+                message: format!("pipeline {}", status),
+                ..Notification::default()
+            };
+            writer.notify(&pipeline).unwrap();
+        }
+
+        assert_eq!(
+            *everything.0.lock().unwrap(),
+            vec![
+                String::from("pipeline success"),
+                String::from("pipeline failed")
+            ]
+        );
+        assert_eq!(
+            *failures.0.lock().unwrap(),
+            vec![String::from("pipeline failed")]
+        );
+    }
+
+    #[test]
+    fn project_prefix() {
+        let recorder = Recorder::default();
+        let mut cfg = config::Config::default();
+        cfg.merge(config::File::from_str(
+            "[projects]\n\"group/api\" = \"[api]\"\nweb = \"🕸\"",
+            config::FileFormat::Toml,
+        ))
+        .unwrap();
+        let mut writer = Tagged {
+            writer: Box::new(recorder.clone()),
+            prefixes: project_prefixes(&cfg).unwrap(),
+        };
+
+        for project in &["Group/API", "other/web", "untagged"] {
+            writer
+                .notify(&Notification {
+                    kind: String::from("push"),
+                    project: Some(String::from(*project)),
+                    message: format!("pushed to {}\n  b6568db: Fix", project),
+                    ..Notification::default()
+                })
+                .unwrap();
+        }
+        writer.write("hello").unwrap();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                String::from("[api] pushed to Group/API\n[api]   b6568db: Fix"),
+                String::from("🕸 pushed to other/web\n🕸   b6568db: Fix"),
+                String::from("pushed to untagged\n  b6568db: Fix"),
+                String::from("hello"),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_for_unknown_backend() {
+        let mut cfg = config::Config::default();
+        cfg.set("service.backends", vec!["stdout"]).unwrap();
+        cfg.set("filters.backends.irc.events", vec!["pipeline"])
+            .unwrap();
+
+        match init(&cfg, Arc::new(SystemClock), &logger()) {
+            Err(Error::Config(e)) => assert!(e.contains("filters for irc")),
+            _ => panic!("expected a config error"),
+        }
+
+        let mut cfg = config::Config::default();
+        cfg.set("service.backends", vec!["stdout"]).unwrap();
+        cfg.set("filters.backends.stdout.events", vec!["pipeline"])
+            .unwrap();
+        assert_eq!(filters(&cfg).unwrap()["stdout"].events, vec!["pipeline"]);
+        assert!(init(&cfg, Arc::new(SystemClock), &logger()).is_ok());
+    }
+
+    #[test]
+    fn stdout_lines() {
+        let mut writer = StdoutWriter { out: Vec::new() };
+        writer.write("one").unwrap();
+        writer.write("two").unwrap();
+        assert_eq!(String::from_utf8(writer.out).unwrap(), "one\ntwo\n");
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::{Instant, SystemTime};
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+/// Where the current time comes from, so that tests can decide what time it is.
+pub trait Clock {
+    /// The time of day, for rendering times.
+    fn system_time(&self) -> SystemTime;
+
+    /// A monotonic instant, for measuring time.
+    fn now(&self) -> Instant;
+}
+
+/// The clock of the system, for everything but tests.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct MockClock {
+    system_time: SystemTime,
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(system_time: SystemTime) -> Self {
+        MockClock {
+            system_time,
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn system_time(&self) -> SystemTime {
+        self.system_time + self.elapsed()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn mock_clock() {
+        let clock = MockClock::new(UNIX_EPOCH);
+        let start = clock.now();
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert_eq!(clock.system_time(), UNIX_EPOCH + Duration::from_secs(90));
+    }
+}
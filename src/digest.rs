@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Put between events sharing a line of a digest.
+const SEPARATOR: &str = " | ";
+
+/// Collects the messages for each channel arriving within a window
+/// after the first one, to send them as one digest instead of many lines.
+pub struct Digest {
+    window: Duration,
+    pending: HashMap<String, Batch>,
+}
+
+struct Batch {
+    opened: Instant,
+    messages: Vec<String>,
+}
+
+impl Digest {
+    pub fn new(window: Duration) -> Self {
+        Digest {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Adds `message` to the digest of `channel`, starting one at `now` if needed.
+    pub fn add(&mut self, channel: &str, message: &str, now: Instant) {
+        self.pending
+            .entry(channel.to_owned())
+            .or_insert_with(|| Batch {
+                opened: now,
+                messages: Vec::new(),
+            })
+            .messages
+            .push(message.to_owned());
+    }
+
+    /// Takes the messages of all channels whose window has passed at `now`.
+    pub fn due(&mut self, now: Instant) -> Vec<(String, Vec<String>)> {
+        let window = self.window;
+        let due = self
+            .pending
+            .iter()
+            .filter(|(_, b)| now >= b.opened + window)
+            .map(|(c, _)| c.clone())
+            .collect::<Vec<_>>();
+
+        due.into_iter()
+            .filter_map(|c| self.pending.remove(&c).map(|b| (c, b.messages)))
+            .collect()
+    }
+}
+
+/// Joins the lines of `messages` on as few lines as possible,
+/// starting a new one whenever the next would not fit in `budget` bytes.
+pub fn combine(messages: &[String], budget: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for part in messages
+        .iter()
+        .flat_map(|m| m.lines())
+        .filter(|l| !l.trim().is_empty())
+    {
+        if let Some(line) = lines
+            .last_mut()
+            .filter(|l| l.len() + SEPARATOR.len() + part.len() <= budget)
+        {
+            line.push_str(SEPARATOR);
+            line.push_str(part);
+        } else {
+            lines.push(part.to_owned());
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_per_channel() {
+        let start = Instant::now();
+        let mut digest = Digest::new(Duration::from_secs(5));
+        digest.add("#one", "first", start);
+        digest.add("#one", "second", start + Duration::from_secs(2));
+        digest.add("#two", "third", start + Duration::from_secs(3));
+
+        assert!(digest.due(start + Duration::from_secs(4)).is_empty());
+        assert_eq!(
+            digest.due(start + Duration::from_secs(5)),
+            vec![(
+                String::from("#one"),
+                vec![String::from("first"), String::from("second")]
+            )]
+        );
+        assert_eq!(
+            digest.due(start + Duration::from_secs(8)),
+            vec![(String::from("#two"), vec![String::from("third")])]
+        );
+        assert!(digest.due(start + Duration::from_secs(60)).is_empty());
+    }
+
+    #[test]
+    fn combine_within_budget() {
+        let messages = vec![
+            String::from("alice pushed"),
+            String::from("bob opened an issue\n\nwith two lines"),
+        ];
+        assert_eq!(
+            combine(&messages, 512),
+            "alice pushed | bob opened an issue | with two lines"
+        );
+        assert_eq!(
+            combine(&messages, 40),
+            "alice pushed | bob opened an issue\nwith two lines"
+        );
+        // lines too long on their own are left for the sender to shorten
+        assert_eq!(
+            combine(&messages, 10),
+            "alice pushed\nbob opened an issue\nwith two lines"
+        );
+    }
+}
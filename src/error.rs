@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("failed to obtain {0} lock")]
+    Lock(&'static str),
+
+    #[error("failed to connect to IRC: {0}")]
+    IrcConnect(String),
+
+    #[error("not connected to IRC")]
+    NotConnected,
+
+    #[error("failed to send IRC message: {0}")]
+    IrcSend(String),
+
+    #[error("rate limit queue for channel {0} is full, dropping message")]
+    RateLimited(String),
+
+    #[error("delivery failed for {} of {total} channels: {}", .errors.len(), .errors.join(", "))]
+    Delivery { total: usize, errors: Vec<String> },
+
+    #[error("delivery failed on {} of {total} networks: {}", .errors.len(), .errors.join(", "))]
+    Networks { total: usize, errors: Vec<String> },
+
+    #[error("backend failed: {0}")]
+    Backend(String),
+
+    #[error("invalid token: {0}")]
+    Token(String),
+
+    #[error("failed to parse Gitlab payload: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("invalid input: {0}")]
+    Input(String),
+
+    #[error("unknown event type {0}")]
+    UnknownEvent(String),
+
+    #[error("failed to shorten URL: {0}")]
+    Shorten(String),
+
+    #[error("request body is larger than {0} bytes")]
+    BodyTooLarge(usize),
+
+    #[error("failed to decompress request body: {0}")]
+    Decompress(String),
+
+    #[error("failed to write journal: {0}")]
+    Journal(#[from] std::io::Error),
+}
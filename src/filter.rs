@@ -0,0 +1,492 @@
+use chrono::{NaiveTime, Timelike};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::glob;
+use crate::notification::Notification;
+
+/// Which events not to send anywhere, read from the `filters` table.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FilterConfig {
+    /// Skip merge requests that are still drafts.
+    #[serde(default)]
+    pub suppress_draft_mr: bool,
+    /// When not to send anything.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    /// Actions on issues to send, leaving out edits and the like.
+    #[serde(default = "default_issue_actions")]
+    pub issue_actions: Vec<String>,
+    /// Actions on merge requests to send.
+    #[serde(default = "default_mr_actions")]
+    pub mr_actions: Vec<String>,
+    /// Statuses of builds to send, leaving out those still on their way.
+    #[serde(default = "default_build_statuses")]
+    pub build_statuses: Vec<String>,
+    /// What may have started pipelines to send, like `push` or `schedule`,
+    /// all of them if empty.
+    #[serde(default)]
+    pub pipeline_sources: Vec<String>,
+    /// Skip pushes whose head commit message contains one of these.
+    #[serde(default = "default_skip_ci_keywords")]
+    pub skip_ci_keywords: Vec<String>,
+    /// Users whose events to skip, which may be patterns like `*-bot`.
+    #[serde(default)]
+    pub ignore_users: Vec<String>,
+    /// Say so on IRC when an event of a kind we have no format for arrives.
+    #[serde(default)]
+    pub notify_unknown: bool,
+}
+
+fn default_issue_actions() -> Vec<String> {
+    vec![
+        String::from("open"),
+        String::from("close"),
+        String::from("reopen"),
+    ]
+}
+
+fn default_build_statuses() -> Vec<String> {
+    vec![String::from("failed")]
+}
+
+fn default_skip_ci_keywords() -> Vec<String> {
+    vec![String::from("[skip ci]"), String::from("[ci skip]")]
+}
+
+fn default_mr_actions() -> Vec<String> {
+    let mut actions = default_issue_actions();
+    actions.push(String::from("merge"));
+    actions.push(String::from("approved"));
+    actions.push(String::from("approval"));
+    actions
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            suppress_draft_mr: false,
+            quiet_hours: None,
+            issue_actions: default_issue_actions(),
+            mr_actions: default_mr_actions(),
+            build_statuses: default_build_statuses(),
+            pipeline_sources: Vec::new(),
+            skip_ci_keywords: default_skip_ci_keywords(),
+            ignore_users: Vec::new(),
+            notify_unknown: false,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Reads the `filters` table, which is optional.
+    pub fn from_config(cfg: &config::Config) -> Result<Self, Error> {
+        match cfg.get::<FilterConfig>("filters") {
+            Ok(filters) => {
+                if let Some(ref quiet) = filters.quiet_hours {
+                    quiet.window()?;
+                }
+                Ok(filters)
+            }
+            Err(config::ConfigError::NotFound(_)) => Ok(FilterConfig::default()),
+            Err(e) => Err(Error::Config(format!(
+                "failed to parse filters config: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Names of the filters that are turned on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut enabled = Vec::new();
+        if self.suppress_draft_mr {
+            enabled.push("suppress_draft_mr");
+        }
+        if self.quiet_hours.is_some() {
+            enabled.push("quiet_hours");
+        }
+        enabled
+    }
+
+    /// Whether the action of `notification` is one to send, which events
+    /// other than issues and merge requests, or without an action, always are.
+    pub fn accepts_action(&self, notification: &Notification) -> bool {
+        let actions = match notification.kind.as_str() {
+            "issue" => &self.issue_actions,
+            "merge_request" => &self.mr_actions,
+            _ => return true,
+        };
+        notification
+            .action
+            .as_ref()
+            .map_or(true, |a| actions.contains(a))
+    }
+
+    /// Whether the status of `notification` is one to send if it is about a build.
+    pub fn accepts_build_status(&self, notification: &Notification) -> bool {
+        if notification.kind != "build" {
+            return true;
+        }
+        notification
+            .status
+            .as_ref()
+            .map_or(true, |s| self.build_statuses.contains(s))
+    }
+
+    /// Whether `notification` is about a pipeline started by one of the
+    /// `pipeline_sources`, which other events and pipelines without a source always are.
+    pub fn accepts_pipeline_source(&self, notification: &Notification) -> bool {
+        if notification.kind != "pipeline" || self.pipeline_sources.is_empty() {
+            return true;
+        }
+        notification
+            .source
+            .as_ref()
+            .map_or(true, |s| self.pipeline_sources.contains(s))
+    }
+
+    /// Whether the head commit `message` of a push asks to skip it.
+    pub fn skips_ci(&self, message: &str) -> bool {
+        let message = message.to_lowercase();
+        self.skip_ci_keywords
+            .iter()
+            .any(|k| message.contains(&k.to_lowercase()))
+    }
+
+    /// Whether `notification` was caused by one of the ignored users.
+    pub fn ignores_user(&self, notification: &Notification) -> bool {
+        let user = match notification.user {
+            Some(ref user) => user.to_lowercase(),
+            None => return false,
+        };
+        self.ignore_users
+            .iter()
+            .any(|pattern| glob::matches(&pattern.to_lowercase(), &user))
+    }
+
+    /// Whether `time` is within the quiet hours, if there are any.
+    pub fn is_quiet(&self, time: SystemTime) -> bool {
+        self.quiet_hours
+            .as_ref()
+            .map_or(false, |q| q.contains(time).unwrap_or(false))
+    }
+}
+
+/// A daily window in which events are held back until it ends, or dropped.
+#[derive(Deserialize, Debug, Clone)]
+pub struct QuietHours {
+    /// Start of the window, like `22:00`.
+    pub start: String,
+    /// End of the window, like `07:00`, the next day if before the start.
+    pub end: String,
+    /// `UTC` or an offset from it like `+02:00`.
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Drop events instead of sending them once the window ends.
+    #[serde(default)]
+    pub drop: bool,
+}
+
+fn default_timezone() -> String {
+    String::from("UTC")
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+impl QuietHours {
+    /// Whether `time` is within the window.
+    pub fn contains(&self, time: SystemTime) -> Result<bool, Error> {
+        let (start, end, offset) = self.window()?;
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let now = ((secs + offset) % SECONDS_PER_DAY + SECONDS_PER_DAY) % SECONDS_PER_DAY;
+
+        Ok(if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        })
+    }
+
+    /// Start and end as seconds since midnight, and the offset from UTC in seconds.
+    fn window(&self) -> Result<(i64, i64, i64), Error> {
+        Ok((
+            time_of_day(&self.start)?,
+            time_of_day(&self.end)?,
+            utc_offset(&self.timezone)?,
+        ))
+    }
+}
+
+fn time_of_day(time: &str) -> Result<i64, Error> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map(|t| i64::from(t.num_seconds_from_midnight()))
+        .map_err(|e| Error::Config(format!("invalid quiet hours time {}: {}", time, e)))
+}
+
+/// Parses `UTC` or offsets like `+02:00` and `-0530` into seconds.
+fn utc_offset(timezone: &str) -> Result<i64, Error> {
+    let invalid = || Error::Config(format!("invalid quiet hours timezone {}", timezone));
+    if timezone == "UTC" || timezone == "Z" {
+        return Ok(0);
+    }
+
+    let sign = match timezone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits = timezone[1..].replace(':', "");
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let hours = digits[..2].parse::<i64>().map_err(|_| invalid())?;
+    let minutes = digits[2..].parse::<i64>().map_err(|_| invalid())?;
+    if hours > 14 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(sign * (hours * 60 + minutes) * 60)
+}
+
+/// What to send to a single channel. Empty lists let everything through,
+/// and events without a branch or status are not held back by those lists,
+/// nor by `default_branch` when the event does not say which that is.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ChannelFilter {
+    /// Event kinds to send, e.g. `pipeline`.
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// Statuses to send, e.g. `failed`.
+    #[serde(default)]
+    pub statuses: Vec<String>,
+    /// Only send events on the default branch of their project.
+    #[serde(default)]
+    pub default_branch: bool,
+}
+
+impl ChannelFilter {
+    pub fn accepts(&self, notification: &Notification) -> bool {
+        fn allowed(list: &[String], value: Option<&String>) -> bool {
+            list.is_empty() || value.map_or(true, |v| list.contains(v))
+        }
+
+        allowed(&self.events, Some(&notification.kind))
+            && allowed(&self.branches, notification.branch.as_ref())
+            && allowed(&self.statuses, notification.status.as_ref())
+            && (!self.default_branch || notification.on_default_branch() != Some(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(branch: &str, status: &str) -> Notification {
+        Notification {
+            kind: String::from("pipeline"),
+            branch: Some(String::from(branch)),
+            status: Some(String::from(status)),
+            ..Notification::default()
+        }
+    }
+
+    fn quiet(start: &str, end: &str, timezone: &str) -> QuietHours {
+        QuietHours {
+            start: String::from(start),
+            end: String::from(end),
+            timezone: String::from(timezone),
+            drop: false,
+        }
+    }
+
+    /// 1970-01-02 at `hour`:`minute` UTC.
+    fn at(hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_secs(86400 + hour * 3600 + minute * 60)
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let overnight = quiet("22:00", "07:00", "UTC");
+        assert!(overnight.contains(at(23, 30)).unwrap());
+        assert!(overnight.contains(at(3, 0)).unwrap());
+        assert!(overnight.contains(at(22, 0)).unwrap());
+        assert!(!overnight.contains(at(7, 0)).unwrap());
+        assert!(!overnight.contains(at(12, 0)).unwrap());
+
+        let lunch = quiet("12:00", "13:00", "UTC");
+        assert!(lunch.contains(at(12, 30)).unwrap());
+        assert!(!lunch.contains(at(13, 30)).unwrap());
+
+        // 20:30 UTC is 22:30 two hours east of it
+        let east = quiet("22:00", "07:00", "+02:00");
+        assert!(east.contains(at(20, 30)).unwrap());
+        assert!(!east.contains(at(19, 30)).unwrap());
+        let west = quiet("22:00", "07:00", "-0500");
+        assert!(west.contains(at(11, 0)).unwrap());
+        assert!(!west.contains(at(12, 0)).unwrap());
+
+        assert!(quiet("25:00", "07:00", "UTC").contains(at(0, 0)).is_err());
+        assert!(quiet("22:00", "07:00", "Europe/Oslo")
+            .contains(at(0, 0))
+            .is_err());
+    }
+
+    #[test]
+    fn quiet_hours_config() {
+        let mut cfg = config::Config::default();
+        cfg.set("filters.quiet_hours.start", "22:00").unwrap();
+        cfg.set("filters.quiet_hours.end", "07:00").unwrap();
+        let filters = FilterConfig::from_config(&cfg).unwrap();
+        assert_eq!(filters.enabled(), vec!["quiet_hours"]);
+        assert!(filters.is_quiet(at(23, 0)));
+        assert!(!filters.is_quiet(at(8, 0)));
+        assert!(!FilterConfig::default().is_quiet(at(23, 0)));
+
+        cfg.set("filters.quiet_hours.end", "later").unwrap();
+        assert!(FilterConfig::from_config(&cfg).is_err());
+    }
+
+    #[test]
+    fn actions() {
+        let event = |kind: &str, action: &str| Notification {
+            kind: String::from(kind),
+            action: Some(String::from(action)),
+            ..Notification::default()
+        };
+
+        let filters = FilterConfig::from_config(&config::Config::default()).unwrap();
+        assert!(filters.accepts_action(&event("issue", "open")));
+        assert!(!filters.accepts_action(&event("issue", "update")));
+        assert!(!filters.accepts_action(&event("issue", "merge")));
+        assert!(filters.accepts_action(&event("merge_request", "merge")));
+        assert!(!filters.accepts_action(&event("merge_request", "update")));
+        assert!(filters.accepts_action(&event("wiki_page", "update")));
+        assert!(filters.accepts_action(&Notification {
+            kind: String::from("issue"),
+            ..Notification::default()
+        }));
+
+        let mut cfg = config::Config::default();
+        cfg.set("filters.mr_actions", vec!["update"]).unwrap();
+        let filters = FilterConfig::from_config(&cfg).unwrap();
+        assert!(filters.accepts_action(&event("merge_request", "update")));
+        assert!(!filters.accepts_action(&event("merge_request", "open")));
+        assert!(!filters.accepts_action(&event("issue", "update")));
+    }
+
+    #[test]
+    fn build_statuses() {
+        let build = |status: &str| Notification {
+            kind: String::from("build"),
+            status: Some(String::from(status)),
+            ..Notification::default()
+        };
+
+        let filters = FilterConfig::default();
+        assert!(filters.accepts_build_status(&build("failed")));
+        assert!(!filters.accepts_build_status(&build("running")));
+        assert!(!filters.accepts_build_status(&build("success")));
+        assert!(filters.accepts_build_status(&pipeline("main", "running")));
+
+        let filters = FilterConfig {
+            build_statuses: vec![String::from("success"), String::from("failed")],
+            ..FilterConfig::default()
+        };
+        assert!(filters.accepts_build_status(&build("success")));
+        assert!(!filters.accepts_build_status(&build("pending")));
+    }
+
+    #[test]
+    fn pipeline_sources() {
+        let from = |source: &str| Notification {
+            source: Some(String::from(source)),
+            ..pipeline("main", "success")
+        };
+
+        let filters = FilterConfig::default();
+        assert!(filters.accepts_pipeline_source(&from("schedule")));
+        assert!(filters.accepts_pipeline_source(&from("push")));
+
+        let filters = FilterConfig {
+            pipeline_sources: vec![String::from("push"), String::from("web")],
+            ..FilterConfig::default()
+        };
+        assert!(filters.accepts_pipeline_source(&from("push")));
+        assert!(filters.accepts_pipeline_source(&from("web")));
+        assert!(!filters.accepts_pipeline_source(&from("schedule")));
+        assert!(filters.accepts_pipeline_source(&pipeline("main", "success")));
+        assert!(filters.accepts_pipeline_source(&Notification {
+            kind: String::from("build"),
+            source: Some(String::from("schedule")),
+            ..Notification::default()
+        }));
+    }
+
+    #[test]
+    fn skip_ci() {
+        let filters = FilterConfig::default();
+        assert!(filters.skips_ci("Update docs [skip ci]"));
+        assert!(filters.skips_ci("[CI SKIP] Bump version"));
+        assert!(!filters.skips_ci("Fix the skip ci parser"));
+
+        let filters = FilterConfig {
+            skip_ci_keywords: Vec::new(),
+            ..FilterConfig::default()
+        };
+        assert!(!filters.skips_ci("Update docs [skip ci]"));
+    }
+
+    #[test]
+    fn ignore_users() {
+        let filters = FilterConfig {
+            ignore_users: vec![String::from("*-bot"), String::from("alice")],
+            ..FilterConfig::default()
+        };
+        let by = |user: &str| Notification {
+            kind: String::from("push"),
+            user: Some(String::from(user)),
+            ..Notification::default()
+        };
+        assert!(filters.ignores_user(&by("deploy-bot")));
+        assert!(filters.ignores_user(&by("Alice")));
+        assert!(!filters.ignores_user(&by("developer")));
+        assert!(!filters.ignores_user(&by("alice2")));
+        assert!(!filters.ignores_user(&Notification::default()));
+    }
+
+    #[test]
+    fn channel_filter() {
+        let everything = ChannelFilter::default();
+        assert!(everything.accepts(&pipeline("main", "success")));
+
+        let failures = ChannelFilter {
+            events: vec![String::from("pipeline")],
+            branches: vec![String::from("main")],
+            statuses: vec![String::from("failed")],
+            default_branch: false,
+        };
+        assert!(failures.accepts(&pipeline("main", "failed")));
+        assert!(!failures.accepts(&pipeline("main", "success")));
+        assert!(!failures.accepts(&pipeline("feature", "failed")));
+        assert!(!failures.accepts(&Notification {
+            kind: String::from("push"),
+            ..Notification::default()
+        }));
+
+        let default_branch = ChannelFilter {
+            default_branch: true,
+            ..ChannelFilter::default()
+        };
+        let on = |branch: &str| Notification {
+            default_branch: Some(String::from("main")),
+            ..pipeline(branch, "failed")
+        };
+        assert!(default_branch.accepts(&on("main")));
+        assert!(!default_branch.accepts(&on("feature")));
+        assert!(default_branch.accepts(&pipeline("feature", "failed")));
+    }
+}
@@ -0,0 +1,429 @@
+use chrono::{DateTime, NaiveDateTime};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::clock::Clock;
+use crate::error::Error;
+
+/// Settings for how events are rendered, read from the `format` table.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FormatConfig {
+    /// Give every user name its own stable color.
+    #[serde(default)]
+    pub color_nicks: bool,
+    /// Put in front of every line sent.
+    #[serde(default)]
+    pub prefix: String,
+    /// Put at the end of every line sent.
+    #[serde(default)]
+    pub suffix: String,
+    /// Replace URLs with short links from the configured shortener.
+    #[serde(default)]
+    pub shorten_urls: bool,
+    /// Color statuses by how well things went.
+    #[serde(default)]
+    pub color_status: bool,
+    /// Show the URL of repositories and projects after their name.
+    #[serde(default = "default_show_repo_url")]
+    pub show_repo_url: bool,
+    /// Show links to issues, merge requests, commits and the like.
+    #[serde(default = "default_show")]
+    pub show_urls: bool,
+    /// Show who did something.
+    #[serde(default = "default_show")]
+    pub show_user: bool,
+    /// Show the repository or project something happened in.
+    #[serde(default = "default_show")]
+    pub show_repo: bool,
+    /// Show how long ago things happened, like `3m ago`, instead of when.
+    #[serde(default)]
+    pub relative_time: bool,
+    /// The shortest sensible line for every event: no links,
+    /// no repository or project and shorter titles.
+    #[serde(default)]
+    pub compact: bool,
+    /// Wraps every message, like `{emoji} [{project}] {body}`, see `Line`.
+    #[serde(default)]
+    pub line_template: Option<String>,
+    /// The time relative times are rendered against, taken from a `Clock`.
+    #[serde(skip)]
+    pub now: Option<SystemTime>,
+}
+
+fn default_show() -> bool {
+    true
+}
+
+fn default_show_repo_url() -> bool {
+    true
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            color_nicks: false,
+            prefix: String::new(),
+            suffix: String::new(),
+            shorten_urls: false,
+            color_status: false,
+            show_repo_url: default_show_repo_url(),
+            show_urls: default_show(),
+            show_user: default_show(),
+            show_repo: default_show(),
+            relative_time: false,
+            compact: false,
+            line_template: None,
+            now: None,
+        }
+    }
+}
+
+impl FormatConfig {
+    /// Reads the `format` table, which is optional.
+    pub fn from_config(cfg: &config::Config) -> Result<Self, Error> {
+        match cfg.get::<FormatConfig>("format") {
+            Ok(format) => Ok(format),
+            Err(config::ConfigError::NotFound(_)) => Ok(FormatConfig::default()),
+            Err(e) => Err(Error::Config(format!(
+                "failed to parse format config: {}",
+                e
+            ))),
+        }
+    }
+}
+
+impl FormatConfig {
+    /// Renders relative times against the time on `clock`.
+    pub fn at(mut self, clock: &Clock) -> Self {
+        self.now = Some(clock.system_time());
+        self
+    }
+
+    /// Whether to show links, which compact lines never have.
+    pub fn links(&self) -> bool {
+        self.show_urls && !self.compact
+    }
+
+    /// How long before `now` the Gitlab `timestamp` was, if relative times are wanted.
+    pub fn ago(&self, timestamp: &str) -> Option<String> {
+        if !self.relative_time {
+            return None;
+        }
+
+        let now = self.now?.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        parse_timestamp(timestamp).map(|then| relative_time(now - then))
+    }
+}
+
+/// Seconds since the epoch of a timestamp in one of the formats Gitlab uses.
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S %z"))
+        .map(|t| t.timestamp())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S UTC").map(|t| t.timestamp())
+        })
+        .ok()
+}
+
+/// A short description of `seconds` ago, like `5m ago`.
+fn relative_time(seconds: i64) -> String {
+    match seconds {
+        s if s < 60 => String::from("just now"),
+        s if s < 60 * 60 => format!("{}m ago", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h ago", s / (60 * 60)),
+        s => format!("{}d ago", s / (24 * 60 * 60)),
+    }
+}
+
+/// Like `fmt::Display`, but with access to the format settings.
+pub trait Format {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result;
+
+    /// Pairs `self` with `cfg` so that it can be used with `write!` and friends.
+    fn with<'a>(&'a self, cfg: &'a FormatConfig) -> Formatted<'a, Self> {
+        Formatted { value: self, cfg }
+    }
+}
+
+pub struct Formatted<'a, T: ?Sized> {
+    value: &'a T,
+    cfg: &'a FormatConfig,
+}
+
+impl<'a, T: Format + ?Sized> fmt::Display for Formatted<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f, self.cfg)
+    }
+}
+
+/// mIRC colors that are readable on both light and dark backgrounds.
+const NICK_COLORS: [u8; 10] = [2, 3, 4, 5, 6, 7, 10, 11, 12, 13];
+
+/// Picks a color for `name`, the same one every time.
+pub fn nick_color(name: &str) -> u8 {
+    // FNV-1a, unlike the std hasher it is guaranteed to never change
+    let hash = name.bytes().fold(0x811c_9dc5u32, |h, b| {
+        (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    NICK_COLORS[hash as usize % NICK_COLORS.len()]
+}
+
+/// A user name, colored when `color_nicks` is set.
+pub struct Nick<'a>(pub &'a str);
+
+impl<'a> Format for Nick<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.color_nicks {
+            write!(f, "\x03{:02}{}\x03", nick_color(self.0), self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Whoever did something followed by a space, left out unless `show_user` is set.
+pub struct Actor<'a, T: ?Sized>(pub &'a T);
+
+impl<'a, T: Format + ?Sized> Format for Actor<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.show_user {
+            write!(f, "{} ", self.0.with(cfg))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Where something happened, like ` on gitlab`, left out
+/// unless `show_repo` is set, and in compact lines.
+pub struct Place<'a, T: ?Sized>(pub &'static str, pub &'a T);
+
+impl<'a, T: Format + ?Sized> Format for Place<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.show_repo && !cfg.compact {
+            write!(f, " {} {}", self.0, self.1.with(cfg))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A URL in parentheses, left out unless `show_urls` is set, and in compact lines.
+pub struct Link<'a>(pub &'a str);
+
+impl<'a> Format for Link<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.links() {
+            write!(f, " ({})", self.0)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Characters of titles kept in compact lines.
+const COMPACT_TITLE_CHARS: usize = 30;
+
+/// The title of an issue, commit and the like, shortened in compact lines.
+pub struct Title<'a>(pub &'a str);
+
+impl<'a> Format for Title<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        match self.0.char_indices().nth(COMPACT_TITLE_CHARS) {
+            Some((end, _)) if cfg.compact => write!(f, "{}...", self.0[..end].trim_end()),
+            _ => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// The pieces of a formatted message, for putting them together
+/// again with a `line_template`.
+pub struct Line<'a> {
+    /// The emoji every message starts with, if it does.
+    pub emoji: &'a str,
+    /// The project of the event, empty if it has none.
+    pub project: &'a str,
+    pub kind: &'a str,
+    /// The rest of the message, including any lines after the first.
+    pub body: &'a str,
+}
+
+impl<'a> Line<'a> {
+    pub fn new(kind: &'a str, project: Option<&'a str>, message: &'a str) -> Self {
+        let (emoji, body) = match message.find(' ') {
+            Some(i) if i > 0 && !message[..i].chars().any(char::is_alphanumeric) => {
+                (&message[..i], &message[i + 1..])
+            }
+            _ => ("", message),
+        };
+        Line {
+            emoji,
+            project: project.unwrap_or_default(),
+            kind,
+            body,
+        }
+    }
+
+    /// Fills in `{emoji}`, `{project}`, `{kind}` and `{body}` in `template`.
+    pub fn render(&self, template: &str) -> String {
+        // the body goes in last, so that braces in it are left alone
+        template
+            .replace("{emoji}", self.emoji)
+            .replace("{project}", self.project)
+            .replace("{kind}", self.kind)
+            .replace("{body}", self.body)
+    }
+}
+
+/// A status like `success` or `failed`, colored when `color_status` is set.
+pub struct Status<'a>(pub &'a str);
+
+impl<'a> Format for Status<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if !cfg.color_status {
+            return write!(f, "{}", self.0);
+        }
+
+        let color = match self.0 {
+            "success" => 3,
+            "failed" => 4,
+            "running" | "created" | "pending" => 7,
+            _ => 14,
+        };
+        write!(f, "\x03{:02}{}\x03", color, self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_template() {
+        let line = Line::new(
+            "push",
+            Some("group/app"),
+            "🌋 alice pushed 2 commits\n  b6568db: Fix",
+        );
+        assert_eq!(line.emoji, "🌋");
+        assert_eq!(line.body, "alice pushed 2 commits\n  b6568db: Fix");
+        assert_eq!(
+            line.render("{emoji} [{project}] {body}"),
+            "🌋 [group/app] alice pushed 2 commits\n  b6568db: Fix"
+        );
+        assert_eq!(
+            line.render("{kind}: {body}"),
+            "push: alice pushed 2 commits\n  b6568db: Fix"
+        );
+
+        let plain = Line::new("note", None, "no emoji {body} here");
+        assert_eq!(plain.emoji, "");
+        assert_eq!(
+            plain.render("[{project}] {body}"),
+            "[] no emoji {body} here"
+        );
+    }
+
+    #[test]
+    fn compact_titles() {
+        let compact = FormatConfig {
+            compact: true,
+            ..FormatConfig::default()
+        };
+        let title = "Fix the crash when the configuration file is missing";
+
+        assert_eq!(
+            Title(title).with(&FormatConfig::default()).to_string(),
+            title
+        );
+        assert_eq!(
+            Title(title).with(&compact).to_string(),
+            "Fix the crash when the configu..."
+        );
+        assert_eq!(Title("Fix typo").with(&compact).to_string(), "Fix typo");
+        assert_eq!(Link("http://example.com").with(&compact).to_string(), "");
+    }
+
+    #[test]
+    fn stable_nick_colors() {
+        assert_eq!(nick_color("Alice"), nick_color("Alice"));
+
+        let colors = ["Alice", "Bob", "Carol", "Dave", "Eve"]
+            .iter()
+            .map(|n| nick_color(n))
+            .collect::<std::collections::HashSet<_>>();
+        assert!(colors.len() > 1);
+    }
+
+    #[test]
+    fn relative_times() {
+        use crate::clock::MockClock;
+        use std::time::Duration;
+
+        // 2016-08-12 15:30:00 UTC
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_471_015_800));
+        let cfg = FormatConfig {
+            relative_time: true,
+            ..FormatConfig::default()
+        }
+        .at(&clock);
+
+        assert_eq!(
+            cfg.ago("2016-08-12 15:29:30 UTC"),
+            Some(String::from("just now"))
+        );
+        assert_eq!(
+            cfg.ago("2016-08-12T17:27:00+02:00"),
+            Some(String::from("3m ago"))
+        );
+        assert_eq!(
+            cfg.ago("2016-08-12 10:00:00 +0000"),
+            Some(String::from("5h ago"))
+        );
+        assert_eq!(
+            cfg.ago("2016-08-09 15:30:00 UTC"),
+            Some(String::from("3d ago"))
+        );
+        assert_eq!(cfg.ago("yesterday"), None);
+
+        let absolute = FormatConfig::default().at(&clock);
+        assert_eq!(absolute.ago("2016-08-12 15:29:30 UTC"), None);
+    }
+
+    #[test]
+    fn colored_nick() {
+        let plain = FormatConfig::default();
+        assert_eq!(Nick("Alice").with(&plain).to_string(), "Alice");
+
+        let colored = FormatConfig {
+            color_nicks: true,
+            ..FormatConfig::default()
+        };
+        assert_eq!(
+            Nick("Alice").with(&colored).to_string(),
+            format!("\x03{:02}Alice\x03", nick_color("Alice"))
+        );
+    }
+
+    #[test]
+    fn colored_status() {
+        let plain = FormatConfig::default();
+        assert_eq!(Status("failed").with(&plain).to_string(), "failed");
+
+        let colored = FormatConfig {
+            color_status: true,
+            ..FormatConfig::default()
+        };
+        assert_eq!(
+            Status("success").with(&colored).to_string(),
+            "\x0303success\x03"
+        );
+        assert_eq!(
+            Status("failed").with(&colored).to_string(),
+            "\x0304failed\x03"
+        );
+    }
+}
@@ -1,59 +1,163 @@
+use serde::Deserialize;
 use serde_json::{error::Error as SerdeError, Value};
 
 use std::fmt;
 
+use crate::error::Error;
+use crate::format::{Actor, Format, FormatConfig, Line, Link, Nick, Place, Status, Title};
+use crate::notification::Notification;
+
 pub fn dispatch<S: AsRef<str>>(
     kind: S,
     data: Value,
+    cfg: &FormatConfig,
+    logger: &slog::Logger,
+) -> Result<String, Error> {
+    let template = match cfg.line_template {
+        Some(ref template) => template,
+        None => return format_event(kind.as_ref(), data, cfg, logger),
+    };
+
+    let project = Notification::new(kind.as_ref(), &data).project;
+    let message = format_event(kind.as_ref(), data, cfg, logger)?;
+    Ok(Line::new(
+        kind.as_ref(),
+        project.as_ref().map(String::as_str),
+        &message,
+    )
+    .render(template))
+}
+
+fn format_event(
+    kind: &str,
+    data: Value,
+    cfg: &FormatConfig,
     logger: &slog::Logger,
-) -> Result<String, String> {
-    match kind.as_ref() {
+) -> Result<String, Error> {
+    match kind {
         "push" => {
             let res: Result<PushEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "tag_push" => {
             let res: Result<TagPushEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "issue" => {
             let res: Result<IssueEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "note" => {
             let res: Result<CommentEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "merge_request" => {
             let res: Result<MergeRequestEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "wiki_page" => {
             let res: Result<WikiEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "pipeline" => {
             let res: Result<PipelineEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
         }
         "build" => {
             let res: Result<BuildEvent, SerdeError> = serde_json::from_value(data);
-            to_string(res, &logger)
+            to_string(res, cfg, &logger)
+        }
+        "deployment" => {
+            let res: Result<DeploymentEvent, SerdeError> = serde_json::from_value(data);
+            to_string(res, cfg, &logger)
         }
-        _ => {
+        "feature_flag" => {
+            let res: Result<FeatureFlagEvent, SerdeError> = serde_json::from_value(data);
+            to_string(res, cfg, &logger)
+        }
+        "member" | "access_request" => {
+            let res: Result<MemberEvent, SerdeError> = serde_json::from_value(data);
+            // access requests look like member events, without saying what happened
+            let res = res.map(|mut member| {
+                if kind == "access_request" {
+                    member.action = String::from("requested");
+                }
+                member
+            });
+            to_string(res, cfg, &logger)
+        }
+        "project_create"
+        | "project_destroy"
+        | "project_rename"
+        | "user_create"
+        | "user_destroy"
+        | "user_add_to_team"
+        | "user_remove_from_team"
+        | "group_create"
+        | "group_destroy" => {
+            let res: Result<SystemHookEvent, SerdeError> = serde_json::from_value(data);
+            to_string(res, cfg, &logger)
+        }
+        k => {
             warn!(logger, "unknown event type");
-            Err(String::from("unknown event type"))
+            Err(Error::UnknownEvent(k.to_owned()))
+        }
+    }
+}
+
+/// Kind of events that neither have an object kind nor a known `X-Gitlab-Event` header.
+const NO_OBJECT_KIND: &str = "no object kind";
+
+/// Maps the value of the `X-Gitlab-Event` header to an object kind.
+fn header_kind(event: &str) -> Option<&'static str> {
+    match event {
+        "Push Hook" => Some("push"),
+        "Tag Push Hook" => Some("tag_push"),
+        "Issue Hook" | "Confidential Issue Hook" => Some("issue"),
+        "Note Hook" | "Confidential Note Hook" => Some("note"),
+        "Merge Request Hook" => Some("merge_request"),
+        "Wiki Page Hook" => Some("wiki_page"),
+        "Pipeline Hook" => Some("pipeline"),
+        "Job Hook" | "Build Hook" => Some("build"),
+        "Feature Flag Hook" => Some("feature_flag"),
+        "Deployment Hook" => Some("deployment"),
+        "Member Hook" => Some("member"),
+        _ => None,
+    }
+}
+
+/// Determines the kind of event in `data`, using the `X-Gitlab-Event`
+/// header when the payload does not say itself.
+pub fn event_kind(event: Option<&str>, data: &Value, logger: &slog::Logger) -> String {
+    let from_header = event.and_then(header_kind);
+
+    // system hooks have no object kind but are keyed by event name
+    let from_body = data["object_kind"]
+        .as_str()
+        .or_else(|| data["event_name"].as_str());
+
+    match (from_body, from_header) {
+        (Some(b), Some(h)) => {
+            if b != h {
+                warn!(logger, "object kind does not match event header";
+                      "object_kind" => b, "event" => event.unwrap_or_default());
+            }
+            b.to_owned()
         }
+        (Some(b), None) => b.to_owned(),
+        (None, Some(h)) => h.to_owned(),
+        (None, None) => NO_OBJECT_KIND.to_owned(),
     }
 }
 
-fn to_string<T: fmt::Display>(
+fn to_string<T: Format>(
     res: Result<T, SerdeError>,
+    cfg: &FormatConfig,
     logger: &slog::Logger,
-) -> Result<String, String> {
-    res.map(|pe| pe.to_string()).map_err(|e| {
+) -> Result<String, Error> {
+    res.map(|pe| pe.with(cfg).to_string()).map_err(|e| {
         warn!(logger, "{}", e);
-        format!("{}", e)
+        Error::from(e)
     })
 }
 
@@ -62,6 +166,61 @@ struct PushEvent {
     user_name: String,
     total_commits_count: u32,
     repository: Repository,
+    #[serde(default)]
+    commits: Vec<PushCommit>,
+    #[serde(default)]
+    before: String,
+    #[serde(default)]
+    after: String,
+    #[serde(default, rename = "ref")]
+    git_ref: String,
+    #[serde(default)]
+    project: Option<Project>,
+}
+
+/// Whether `sha` is the all zeros SHA Gitlab uses as the before or after
+/// of a push when a ref is created or deleted, of any length since
+/// SHA-256 repositories have longer ones.
+fn is_null_sha(sha: &str) -> bool {
+    sha.len() >= 7 && sha.chars().all(|c| c == '0')
+}
+
+/// The abbreviated form of `sha`, the same for SHA-1 and SHA-256 ids.
+fn short_sha(sha: &str) -> &str {
+    sha.get(..7).unwrap_or(sha)
+}
+
+impl PushEvent {
+    /// Link to the full diff of the push, if it is worth one.
+    fn compare_url(&self) -> Option<String> {
+        if self.total_commits_count < 2
+            || self.before.is_empty()
+            || self.after.is_empty()
+            || is_null_sha(&self.before)
+            || is_null_sha(&self.after)
+        {
+            return None;
+        }
+
+        Some(format!(
+            "{}/-/compare/{}...{}",
+            self.repository.homepage.trim_end_matches('/'),
+            self.before,
+            self.after
+        ))
+    }
+
+    /// Whether this went to the default branch of the project, if Gitlab said which one it is.
+    fn on_default_branch(&self) -> bool {
+        let default_branch = self
+            .project
+            .as_ref()
+            .and_then(|p| p.default_branch.as_ref())
+            .or_else(|| self.repository.default_branch.as_ref());
+        default_branch.map_or(false, |b| {
+            self.git_ref.trim_start_matches("refs/heads/") == b.as_str()
+        })
+    }
 }
 
 #[derive(Deserialize)]
@@ -86,6 +245,30 @@ struct CommentEvent {
     user: User,
     #[serde(rename = "object_attributes")]
     comment: Comment,
+    snippet: Option<Snippet>,
+    /// The commit of comments on one, which may only have its id.
+    #[serde(default)]
+    commit: Option<NotedCommit>,
+}
+
+#[derive(Deserialize)]
+struct NotedCommit {
+    #[serde(default)]
+    id: Option<String>,
+}
+
+impl CommentEvent {
+    /// The commit commented on, if it is one and Gitlab says which.
+    fn commit_sha(&self) -> Option<&str> {
+        if self.comment.noteable_type != "Commit" {
+            return None;
+        }
+        self.commit
+            .as_ref()
+            .and_then(|c| c.id.as_ref())
+            .or_else(|| self.comment.commit_id.as_ref())
+            .map(String::as_str)
+    }
 }
 
 #[derive(Deserialize)]
@@ -117,9 +300,95 @@ struct BuildEvent {
     build_name: String,
     build_stage: String,
     build_status: String,
+    #[serde(default)]
+    build_started_at: Option<String>,
+    #[serde(default)]
+    build_finished_at: Option<String>,
     repository: Repository,
 }
 
+#[derive(Deserialize)]
+struct DeploymentEvent {
+    user: User,
+    status: String,
+    environment: String,
+    short_sha: String,
+    project: Project,
+}
+
+#[derive(Deserialize)]
+struct FeatureFlagEvent {
+    #[serde(rename = "object_attributes")]
+    feature_flag: FeatureFlag,
+    project: Project,
+}
+
+#[derive(Deserialize)]
+struct FeatureFlag {
+    name: String,
+    active: bool,
+}
+
+#[derive(Deserialize)]
+struct MemberEvent {
+    user_username: String,
+    access_level: String,
+    #[serde(default = "default_member_action")]
+    action: String,
+    project_path: String,
+}
+
+fn default_member_action() -> String {
+    String::from("added")
+}
+
+/// Events sent by instance wide system hooks, these are
+/// keyed by `event_name` instead of `object_kind`.
+#[derive(Deserialize)]
+#[serde(tag = "event_name", rename_all = "snake_case")]
+enum SystemHookEvent {
+    ProjectCreate(ProjectHook),
+    ProjectDestroy(ProjectHook),
+    ProjectRename(ProjectRenameHook),
+    UserCreate(UserHook),
+    UserDestroy(UserHook),
+    UserAddToTeam(TeamMemberHook),
+    UserRemoveFromTeam(TeamMemberHook),
+    GroupCreate(GroupHook),
+    GroupDestroy(GroupHook),
+}
+
+#[derive(Deserialize)]
+struct ProjectHook {
+    owner_name: String,
+    path_with_namespace: String,
+}
+
+#[derive(Deserialize)]
+struct ProjectRenameHook {
+    old_path_with_namespace: String,
+    path_with_namespace: String,
+}
+
+#[derive(Deserialize)]
+struct UserHook {
+    name: String,
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct TeamMemberHook {
+    user_name: String,
+    project_path_with_namespace: String,
+    access_level: String,
+}
+
+#[derive(Deserialize)]
+struct GroupHook {
+    name: String,
+    path: String,
+}
+
 #[derive(Deserialize)]
 struct User {
     name: String,
@@ -131,12 +400,17 @@ struct Issue {
     url: String,
     #[serde(default = "default_action")]
     action: String,
+    /// What the issue ended up as, like `opened` or `closed`.
+    #[serde(default)]
+    state: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct Repository {
     name: String,
     homepage: String,
+    #[serde(default)]
+    default_branch: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -144,14 +418,90 @@ struct Comment {
     noteable_type: String,
     url: String,
     note: String,
+    #[serde(default)]
+    commit_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Snippet {
+    title: String,
 }
 
 #[derive(Deserialize)]
 struct MergeRequest {
     title: String,
+    #[serde(default)]
+    iid: Option<u64>,
     #[serde(default = "default_action")]
     action: String,
     url: String,
+    #[serde(default)]
+    work_in_progress: bool,
+    #[serde(default)]
+    merge_status: Option<String>,
+    #[serde(default)]
+    merge_when_pipeline_succeeds: bool,
+    /// What the merge request ended up as, like `opened` or `merged`.
+    #[serde(default)]
+    state: Option<String>,
+}
+
+impl MergeRequest {
+    /// A short note on whether the merge request can go in, only for
+    /// actions where it is still open.
+    fn merge_note(&self) -> Option<&'static str> {
+        if !["open", "reopen", "update"].contains(&self.action.as_str()) {
+            return None;
+        }
+
+        if self.merge_status.as_ref().map(String::as_str) == Some("cannot_be_merged") {
+            Some("blocked: unmergeable")
+        } else if self.merge_when_pipeline_succeeds {
+            Some("auto-merge on green")
+        } else {
+            None
+        }
+    }
+
+    /// `approved` or `unapproved` for the actions of merge request
+    /// approvals, which depending on the Gitlab version come as either.
+    fn approval(&self) -> Option<&'static str> {
+        match self.action.as_str() {
+            "approved" | "approval" => Some("approved"),
+            "unapproved" | "unapproval" => Some("unapproved"),
+            _ => None,
+        }
+    }
+
+    fn is_draft(&self) -> bool {
+        const PREFIXES: [&str; 4] = ["Draft:", "[Draft]", "(Draft)", "WIP:"];
+        self.work_in_progress
+            || PREFIXES
+                .iter()
+                .any(|p| self.title.to_lowercase().starts_with(&p.to_lowercase()))
+    }
+}
+
+/// The message of the commit a push ends at, if `data` is a push with one.
+pub fn head_commit_message<'a>(kind: &str, data: &'a Value) -> Option<&'a str> {
+    if kind != "push" {
+        return None;
+    }
+    let commits = data["commits"].as_array()?;
+    commits
+        .iter()
+        .find(|c| c["id"] == data["after"])
+        .or_else(|| commits.last())
+        .and_then(|c| c["message"].as_str())
+}
+
+/// Whether `data` is about a merge request that is still a draft. Marking
+/// a merge request as ready makes it no longer one, so that gets through.
+pub fn is_draft_merge_request(kind: &str, data: &Value) -> bool {
+    kind == "merge_request"
+        && MergeRequest::deserialize(&data["object_attributes"])
+            .map(|mr| mr.is_draft())
+            .unwrap_or(false)
 }
 
 #[derive(Deserialize)]
@@ -162,12 +512,27 @@ struct WikiEditEvent {
     url: String,
 }
 
+#[derive(Deserialize)]
+struct PushCommit {
+    id: String,
+    message: String,
+    #[serde(default)]
+    timestamp: Option<String>,
+    #[serde(default)]
+    author: Option<User>,
+}
+
 #[derive(Deserialize)]
 struct Commit<T> {
     id: T,
     #[serde(default)]
     sha: String,
     message: String,
+    /// The first line of the message, which pipelines have.
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    author: Option<User>,
     #[serde(default)]
     url: String,
 }
@@ -175,34 +540,123 @@ struct Commit<T> {
 #[derive(Deserialize)]
 struct Pipeline {
     status: String,
+    /// What started the pipeline, like `push` or `schedule`.
+    #[serde(default)]
+    source: Option<String>,
     #[serde(default)]
     duration: usize,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    finished_at: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct Project {
     name: String,
     web_url: String,
+    #[serde(default)]
+    default_branch: Option<String>,
 }
 
 fn default_action() -> String {
     String::from("create")
 }
 
-impl fmt::Display for PushEvent {
+/// `action` as something that happened, like `closed` for `close`.
+fn past_tense(action: &str) -> String {
+    if action.ends_with("ed") {
+        action.to_owned()
+    } else if action.ends_with('e') {
+        format!("{}d", action)
+    } else {
+        format!("{}ed", action)
+    }
+}
+
+/// Where an issue or merge request ended up, for the
+/// actions that do not say so themselves.
+fn resulting_state<'a>(action: &str, state: &'a Option<String>) -> Option<&'a str> {
+    match (action, state) {
+        ("update", Some(state)) => Some(state),
+        _ => None,
+    }
+}
+
+/// Renders as ` → closed` for the state things ended up in, if any.
+struct State<'a>(Option<&'a str>);
+
+impl<'a> fmt::Display for State<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(state) => write!(f, " → {}", state),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Format for PushEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         write!(
             f,
-            "🌋 {} pushed {} commits to {}",
-            self.user_name, self.total_commits_count, self.repository
-        )
+            "🌋 {}pushed {} commits{}",
+            Actor(&Nick(&self.user_name)).with(cfg),
+            self.total_commits_count,
+            Place("to", &self.repository).with(cfg)
+        )?;
+        if self.on_default_branch() {
+            write!(f, " on the default branch")?;
+        }
+        if let Some(url) = self.compare_url() {
+            if cfg.links() {
+                write!(f, " {}", url)?;
+            }
+        }
+        if cfg.compact {
+            return Ok(());
+        }
+
+        // only list the first few, the rest can be found in Gitlab
+        const NCOMMITS: usize = 3;
+        for commit in self.commits.iter().take(NCOMMITS) {
+            write!(f, "\n  {}", commit.with(cfg))?;
+        }
+        if self.commits.len() > NCOMMITS {
+            write!(f, "\n  ... and {} more", self.commits.len() - NCOMMITS)?;
+        }
+
+        Ok(())
     }
 }
 
-impl fmt::Display for TagPushEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for PushCommit {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        let first_line = self.message.split('\n').nth(0).unwrap_or("<invalid>");
+        write!(f, "{}", short_sha(&self.id))?;
+        if let Some(ref author) = self.author {
+            if cfg.show_user {
+                write!(f, " by {}", author.with(cfg))?;
+            }
+        }
+        // only the date, the time of day is just noise
+        if let Some(ref timestamp) = self.timestamp {
+            match cfg.ago(timestamp) {
+                Some(ago) => write!(f, " {}", ago)?,
+                None => write!(
+                    f,
+                    " on {}",
+                    timestamp.split('T').nth(0).unwrap_or(timestamp)
+                )?,
+            }
+        }
+        write!(f, ": {}", first_line)
+    }
+}
+
+impl Format for TagPushEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         let tag_name = self.tag_ref.rsplit('/').nth(0).unwrap_or("<invalid>");
-        let action_text = if self.before == "0000000000000000000000000000000000000000" {
+        let action_text = if is_null_sha(&self.before) {
             "pushed"
         } else {
             "deleted"
@@ -210,133 +664,361 @@ impl fmt::Display for TagPushEvent {
 
         write!(
             f,
-            "🔖 {} {} tag \"{}\" to {}",
-            self.user_name, action_text, tag_name, self.repository,
+            "🔖 {}{} tag \"{}\"{}",
+            Actor(&Nick(&self.user_name)).with(cfg),
+            action_text,
+            tag_name,
+            Place("to", &self.repository).with(cfg),
         )
     }
 }
 
-impl fmt::Display for IssueEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for IssueEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         write!(
             f,
-            "🐛 {} {} on {}",
-            self.user, self.issue, self.repository
+            "🐛 {}{}{}",
+            Actor(&self.user).with(cfg),
+            self.issue.with(cfg),
+            Place("on", &self.repository).with(cfg)
         )
     }
 }
 
-impl fmt::Display for MergeRequestEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for MergeRequestEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        let emoji = match self.merge_request.approval() {
+            Some("approved") => "✅",
+            _ => "🚓",
+        };
         write!(
             f,
-            "🚓 {} {} on {}",
-            self.user, self.merge_request, self.repository
+            "{} {}{}{}",
+            emoji,
+            Actor(&self.user).with(cfg),
+            self.merge_request.with(cfg),
+            Place("on", &self.repository).with(cfg)
         )
     }
 }
 
-impl fmt::Display for WikiEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "📰 {} {}", self.user, self.wiki_edit)
+impl Format for WikiEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        write!(
+            f,
+            "📰 {}{}",
+            Actor(&self.user).with(cfg),
+            self.wiki_edit.with(cfg)
+        )
     }
 }
 
-impl fmt::Display for CommentEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "💬 {} {}", self.user, self.comment)
+impl Format for CommentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        match self.snippet {
+            Some(ref snippet) if self.comment.noteable_type == "Snippet" => {
+                write!(
+                    f,
+                    "💬 {}commented on snippet \"{}\"",
+                    Actor(&self.user).with(cfg),
+                    Title(&snippet.title).with(cfg),
+                )?;
+                if cfg.links() {
+                    write!(f, " {}", self.comment.url)?;
+                }
+                write!(f, ": {}", Title(&self.comment.excerpt()).with(cfg))
+            }
+            _ => {
+                write!(
+                    f,
+                    "💬 {}commented on {}",
+                    Actor(&self.user).with(cfg),
+                    self.comment.noteable_type.to_lowercase()
+                )?;
+                if let Some(sha) = self.commit_sha() {
+                    write!(f, " {}", short_sha(sha))?;
+                }
+                if cfg.links() {
+                    write!(f, " {}", self.comment.url)?;
+                }
+                write!(f, ": {}", Title(&self.comment.excerpt()).with(cfg))
+            }
+        }
     }
 }
 
-impl fmt::Display for PipelineEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for PipelineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         write!(
             f,
-            "👷 {} on {} for {}",
-            self.pipeline, self.commit, self.project
+            "👷 {} for {}{}",
+            self.pipeline.with(cfg),
+            self.commit.with(cfg),
+            Place("on", &self.project).with(cfg)
         )
     }
 }
 
-impl fmt::Display for BuildEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for BuildEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         write!(
             f,
-            "🚛 Build {} ({}) {} on {} for {}",
-            self.build_name, self.build_stage, self.build_status, self.commit, self.repository
+            "🚛 Build {} ({}) {}{} on {}{}",
+            self.build_name,
+            self.build_stage,
+            self.build_status,
+            self.build_finished_at
+                .as_ref()
+                .or_else(|| self.build_started_at.as_ref())
+                .and_then(|t| cfg.ago(t))
+                .map(|ago| format!(" {}", ago))
+                .unwrap_or_default(),
+            self.commit,
+            Place("for", &self.repository).with(cfg)
         )
     }
 }
 
-impl fmt::Display for User {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.name)
+impl Format for DeploymentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        write!(
+            f,
+            "🚀 {}deployed {} to {}{}: {}",
+            Actor(&self.user).with(cfg),
+            self.short_sha,
+            self.environment,
+            Place("on", &self.project).with(cfg),
+            Status(&self.status).with(cfg)
+        )
     }
 }
 
-impl fmt::Display for Commit<String> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let first_line = self.message.split('\n').nth(0).unwrap_or("<invalid>");
-        let mut shortid = self.id.clone();
-        shortid.truncate(7);
-        write!(f, "{}: {} ({})", shortid, first_line, self.url)
+impl Format for FeatureFlagEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        write!(
+            f,
+            "🚩 Feature flag \"{}\" {}{}",
+            self.feature_flag.name,
+            if self.feature_flag.active {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            Place("on", &self.project).with(cfg)
+        )
+    }
+}
+
+impl Format for MemberEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        match self.action.as_str() {
+            "removed" => write!(
+                f,
+                "👤 {} removed as {} from {}",
+                Nick(&self.user_username).with(cfg),
+                self.access_level,
+                self.project_path
+            ),
+            "requested" => write!(
+                f,
+                "👤 {} requested {} access to {}",
+                Nick(&self.user_username).with(cfg),
+                self.access_level,
+                self.project_path
+            ),
+            action => write!(
+                f,
+                "👤 {} {} as {} to {}",
+                Nick(&self.user_username).with(cfg),
+                action,
+                self.access_level,
+                self.project_path
+            ),
+        }
+    }
+}
+
+impl Format for SystemHookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        match self {
+            SystemHookEvent::ProjectCreate(p) => write!(
+                f,
+                "🏗 {} created project {}",
+                Nick(&p.owner_name).with(cfg),
+                p.path_with_namespace
+            ),
+            SystemHookEvent::ProjectDestroy(p) => write!(
+                f,
+                "🗑 {} deleted project {}",
+                Nick(&p.owner_name).with(cfg),
+                p.path_with_namespace
+            ),
+            SystemHookEvent::ProjectRename(p) => write!(
+                f,
+                "🏷 Project {} renamed to {}",
+                p.old_path_with_namespace, p.path_with_namespace
+            ),
+            SystemHookEvent::UserCreate(u) => {
+                write!(
+                    f,
+                    "👤 User {} ({}) created",
+                    Nick(&u.name).with(cfg),
+                    u.username
+                )
+            }
+            SystemHookEvent::UserDestroy(u) => {
+                write!(
+                    f,
+                    "👤 User {} ({}) deleted",
+                    Nick(&u.name).with(cfg),
+                    u.username
+                )
+            }
+            SystemHookEvent::UserAddToTeam(m) => write!(
+                f,
+                "👥 {} added to project {} as {}",
+                Nick(&m.user_name).with(cfg),
+                m.project_path_with_namespace,
+                m.access_level
+            ),
+            SystemHookEvent::UserRemoveFromTeam(m) => write!(
+                f,
+                "👥 {} removed from project {}",
+                Nick(&m.user_name).with(cfg),
+                m.project_path_with_namespace
+            ),
+            SystemHookEvent::GroupCreate(g) => {
+                write!(f, "🏢 Group {} ({}) created", g.name, g.path)
+            }
+            SystemHookEvent::GroupDestroy(g) => {
+                write!(f, "🏢 Group {} ({}) deleted", g.name, g.path)
+            }
+        }
+    }
+}
+
+impl Format for User {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        write!(f, "{}", Nick(&self.name).with(cfg))
+    }
+}
+
+impl Format for Commit<String> {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        let title = match self.title {
+            Some(ref title) => title.as_str(),
+            None => self.message.split('\n').nth(0).unwrap_or("<invalid>"),
+        };
+        write!(f, "{} \"{}\"", short_sha(&self.id), Title(title).with(cfg))?;
+        if let Some(ref author) = self.author {
+            if cfg.show_user {
+                write!(f, " by {}", author.with(cfg))?;
+            }
+        }
+        write!(f, "{}", Link(&self.url).with(cfg))
     }
 }
 
 impl fmt::Display for Commit<u32> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let first_line = self.message.split('\n').nth(0).unwrap_or("<invalid>");
-        let mut shortid = self.sha.clone();
-        shortid.truncate(7);
-        write!(f, "{}: {}", shortid, first_line)
+        write!(f, "{}: {}", short_sha(&self.sha), first_line)
     }
 }
 
-impl fmt::Display for Issue {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         write!(
             f,
-            "{}ed issue \"{}\" ({})",
-            self.action, self.title, self.url
+            "{} issue \"{}\"{}{}",
+            past_tense(&self.action),
+            Title(&self.title).with(cfg),
+            State(resulting_state(&self.action, &self.state)),
+            Link(&self.url).with(cfg)
         )
     }
 }
 
-impl fmt::Display for MergeRequest {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for MergeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        // approvals name the merge request like Gitlab does in its own notifications
+        if let Some(approval) = self.approval() {
+            write!(f, "{} merge request", approval)?;
+            if let Some(iid) = self.iid {
+                write!(f, " !{}", iid)?;
+            }
+            return write!(
+                f,
+                " \"{}\"{}",
+                Title(&self.title).with(cfg),
+                Link(&self.url).with(cfg)
+            );
+        }
+
         write!(
             f,
-            "{}ed merge request \"{}\" ({})",
-            self.action, self.title, self.url
-        )
+            "{} merge request \"{}\"{}{}",
+            past_tense(&self.action),
+            Title(&self.title).with(cfg),
+            State(resulting_state(&self.action, &self.state)),
+            Link(&self.url).with(cfg)
+        )?;
+        match self.merge_note() {
+            Some(note) => write!(f, " ({})", note),
+            None => Ok(()),
+        }
     }
 }
 
-impl fmt::Display for Repository {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} ({})", self.name, self.homepage)
+impl Format for Repository {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.show_repo_url && cfg.links() {
+            write!(f, "{} ({})", self.name, self.homepage)
+        } else {
+            write!(f, "{}", self.name)
+        }
     }
 }
 
-impl fmt::Display for Project {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} ({})", self.name, self.web_url)
+impl Format for Project {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
+        if cfg.show_repo_url && cfg.links() {
+            write!(f, "{} ({})", self.name, self.web_url)
+        } else {
+            write!(f, "{}", self.name)
+        }
     }
 }
 
-impl fmt::Display for Pipeline {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         let duration = if self.duration > 0 {
             format!(" in {} seconds", self.duration)
         } else {
             String::new()
         };
-        write!(f, "Pipeline {}{}", self.status, duration)
+        // pushes start most pipelines, so only say so for the others
+        let source = match self.source.as_ref().map(String::as_str) {
+            None | Some("") | Some("push") => String::new(),
+            Some("schedule") => String::from(" (scheduled)"),
+            Some("merge_request_event") => String::from(" (merge request)"),
+            Some(s) => format!(" ({})", s.replace('_', " ")),
+        };
+        write!(f, "Pipeline {}{}{}", self.status, source, duration)?;
+
+        let when = self
+            .finished_at
+            .as_ref()
+            .or_else(|| self.created_at.as_ref());
+        match when.and_then(|t| cfg.ago(t)) {
+            Some(ago) => write!(f, ", {}", ago),
+            None => Ok(()),
+        }
     }
 }
 
-impl fmt::Display for WikiEditEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Format for WikiEditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter, cfg: &FormatConfig) -> fmt::Result {
         let ending = if self.action.ends_with('e') {
             "d"
         } else {
@@ -344,57 +1026,226 @@ impl fmt::Display for WikiEditEvent {
         };
         write!(
             f,
-            "{}{} wiki page \"{}\" ({})",
-            self.action, ending, self.title, self.url
+            "{}{} wiki page \"{}\"{}",
+            self.action,
+            ending,
+            Title(&self.title).with(cfg),
+            Link(&self.url).with(cfg)
         )
     }
 }
 
-impl fmt::Display for Comment {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut msg = self.note.clone();
-
-        // only show the first 40 chars
+impl Comment {
+    /// The start of the note, shortened to fit a single line.
+    fn excerpt(&self) -> String {
+        // only show the first 40 chars, counting characters rather
+        // than bytes so that notes are never cut inside of one
         const NCHARS: usize = 40;
-        if self.note.len() > NCHARS {
-            msg.truncate(NCHARS);
+        if self.note.chars().count() > NCHARS {
+            let mut msg = self.note.chars().take(NCHARS).collect::<String>();
             msg = msg.trim_end().to_owned();
             msg.push_str("...");
+            msg
+        } else {
+            self.note.clone()
         }
-        write!(
-            f,
-            "commented on {} {}: {}",
-            self.noteable_type.to_lowercase(),
-            self.url,
-            msg,
-        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
+    use crate::clock::MockClock;
+    use serde_json::json;
+    use std::{
+        fs::File,
+        time::{Duration, UNIX_EPOCH},
+    };
 
     #[test]
     fn push() {
         let tp = "push";
         let d = serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("pushed"));
         assert!(s.contains("commits to"));
     }
 
+    #[test]
+    fn show_repo_url() {
+        let d: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let s = dispatch("push", d.clone(), &FormatConfig::default(), &log).unwrap();
+        assert!(s.contains("to Diaspora (http://example.com/mike/diaspora)"));
+
+        let cfg = FormatConfig {
+            show_repo_url: false,
+            ..FormatConfig::default()
+        };
+        let s = dispatch("push", d, &cfg, &log).unwrap();
+        assert!(s.lines().nth(0).unwrap().contains("to Diaspora"));
+        assert!(!s.contains("(http://example.com/mike/diaspora)"));
+    }
+
+    #[test]
+    fn show_project_url() {
+        let d: Value =
+            serde_json::from_reader(File::open("test/pipeline.json").expect("find file")).unwrap();
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let s = dispatch("pipeline", d.clone(), &FormatConfig::default(), &log).unwrap();
+        assert!(s.ends_with("(http://192.168.64.1:3005/gitlab-org/gitlab-test)"));
+
+        let cfg = FormatConfig {
+            show_repo_url: false,
+            ..FormatConfig::default()
+        };
+        let s = dispatch("pipeline", d, &cfg, &log).unwrap();
+        assert!(s.ends_with("for Gitlab Test"));
+    }
+
+    #[test]
+    fn push_default_branch() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let s = dispatch("push", d.clone(), &FormatConfig::default(), &log).unwrap();
+        assert!(s.contains("on the default branch"));
+
+        d["ref"] = json!("refs/heads/feature");
+        let s = dispatch("push", d.clone(), &FormatConfig::default(), &log).unwrap();
+        assert!(!s.contains("default branch"));
+
+        // without a project, the repository may say which branch it is
+        d["ref"] = json!("refs/heads/master");
+        d.as_object_mut().unwrap().remove("project");
+        let s = dispatch("push", d.clone(), &FormatConfig::default(), &log).unwrap();
+        assert!(!s.contains("default branch"));
+        d["repository"]["default_branch"] = json!("master");
+        let s = dispatch("push", d, &FormatConfig::default(), &log).unwrap();
+        assert!(s.contains("on the default branch"));
+    }
+
+    #[test]
+    fn push_compare_url() {
+        let tp = "push";
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+
+        let s = dispatch(
+            tp,
+            d.clone(),
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert!(s.lines().nth(0).unwrap().ends_with(
+            "http://example.com/mike/diaspora/-/compare/\
+             95790bf891e76fee5e1747ab589903a6a1f80f22...da1560886d4f094c3e6c9ef40349f7d38b5d27d7"
+        ));
+
+        // a new branch has nothing to compare with
+        d["before"] = Value::from("0000000000000000000000000000000000000000");
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert!(!s.contains("/-/compare/"));
+    }
+
+    #[test]
+    fn null_sha() {
+        assert!(is_null_sha("0000000000000000000000000000000000000000"));
+        assert!(is_null_sha(
+            "0000000000000000000000000000000000000000000000000000000000000000"
+        ));
+        assert!(!is_null_sha("da1560886d4f094c3e6c9ef40349f7d38b5d27d7"));
+        assert!(!is_null_sha("0000000000000000000000000000000000000001"));
+        assert!(!is_null_sha("000000"));
+        assert!(!is_null_sha(""));
+    }
+
+    #[test]
+    fn sha256_commits() {
+        let sha = "b6568db1bc1dcd7f8b4d5a946b0b91f9dacd7327b6568db1bc1dcd7f8b4d5a94";
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+        d["commits"][0]["id"] = Value::from(sha);
+        d["after"] = Value::from(sha);
+
+        let s = dispatch(
+            "push",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert!(s.contains("\n  b6568db by "));
+        assert!(s.contains(&format!("...{}", sha)));
+
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/push_tag.json").expect("find file")).unwrap();
+        d["before"] = Value::from("0".repeat(64));
+        let s = dispatch(
+            "tag_push",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert!(s.contains("pushed tag \"v1.0.0\""));
+    }
+
+    #[test]
+    fn push_commit_listing() {
+        let tp = "push";
+        let d = serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        let lines = s.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "  b6568db by Jordi Mallach on 2011-12-12: Update Catalan translation to e38cb41."
+        );
+        assert_eq!(
+            lines[2],
+            "  da15608 by GitLab dev user on 2012-01-03: fixed readme"
+        );
+    }
+
     #[test]
     fn push_tag() {
         let tp = "tag_push";
         let d =
             serde_json::from_reader(File::open("test/push_tag.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("pushed tag \"v1.0.0\""));
@@ -405,32 +1256,107 @@ mod tests {
         let tp = "issue";
         let d = serde_json::from_reader(File::open("test/issue.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("opened issue"));
     }
 
+    #[test]
+    fn format_toggles() {
+        let format = |cfg: FormatConfig| {
+            let d =
+                serde_json::from_reader(File::open("test/issue.json").expect("find file")).unwrap();
+            dispatch("issue", d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert_eq!(
+            format(FormatConfig::default()),
+            "🐛 Administrator opened issue \"New API: create/update/delete file\" \
+             (http://example.com/diaspora/issues/23) on Gitlab Test \
+             (http://example.com/gitlabhq/gitlab-test)"
+        );
+        assert_eq!(
+            format(FormatConfig {
+                show_urls: false,
+                ..FormatConfig::default()
+            }),
+            "🐛 Administrator opened issue \"New API: create/update/delete file\" on Gitlab Test"
+        );
+        assert_eq!(
+            format(FormatConfig {
+                show_user: false,
+                ..FormatConfig::default()
+            }),
+            "🐛 opened issue \"New API: create/update/delete file\" \
+             (http://example.com/diaspora/issues/23) on Gitlab Test \
+             (http://example.com/gitlabhq/gitlab-test)"
+        );
+        assert_eq!(
+            format(FormatConfig {
+                show_repo: false,
+                ..FormatConfig::default()
+            }),
+            "🐛 Administrator opened issue \"New API: create/update/delete file\" \
+             (http://example.com/diaspora/issues/23)"
+        );
+    }
+
     #[test]
     fn commit_comment() {
         let tp = "note";
         let d = serde_json::from_reader(File::open("test/comment_commit.json").expect("find file"))
             .unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("commented on"));
         assert!(s.contains("commit"));
     }
 
+    #[test]
+    fn commit_comment_sha() {
+        let format = |file: &str| {
+            let d = serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            let cfg = FormatConfig {
+                show_urls: false,
+                ..FormatConfig::default()
+            };
+            dispatch("note", d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert!(format("test/comment_commit.json").starts_with(
+            "💬 Administrator commented on commit cfe32cf: This is a commit comment."
+        ));
+        // the commit may only have its id, with none in the note itself
+        assert!(format("test/comment_commit_partial.json")
+            .starts_with("💬 Administrator commented on commit 9a0e5b3: Why is this needed?"));
+        assert!(format("test/comment_issue.json").contains("commented on issue: "));
+    }
+
     #[test]
     fn mr_comment() {
         let tp = "note";
         let d = serde_json::from_reader(File::open("test/comment_mr.json").expect("find file"))
             .unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("commented on"));
@@ -443,13 +1369,36 @@ mod tests {
         let d = serde_json::from_reader(File::open("test/comment_issue.json").expect("find file"))
             .unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("commented on"));
         assert!(s.contains("issue"));
     }
 
+    #[test]
+    fn multibyte_comment() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/comment_issue.json").expect("find file"))
+                .unwrap();
+        // byte 40 is in the middle of a character
+        d["object_attributes"]["note"] = Value::from(format!("a{}", "ö".repeat(45)));
+
+        let s = dispatch(
+            "note",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        assert!(s.contains(&format!("a{}...", "ö".repeat(39))));
+    }
+
     #[test]
     fn snippet_comment() {
         let tp = "note";
@@ -457,7 +1406,12 @@ mod tests {
             serde_json::from_reader(File::open("test/comment_snippet.json").expect("find file"))
                 .unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
         assert!(s.contains("commented on"));
@@ -465,17 +1419,334 @@ mod tests {
         assert!(s.ends_with("supposed..."));
     }
 
+    #[test]
+    fn snippet_comment_title() {
+        let tp = "note";
+        let d = serde_json::from_reader(
+            File::open("test/comment_snippet_title.json").expect("find file"),
+        )
+        .unwrap();
+
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("commented on snippet \"Hello world in Ruby\""));
+    }
+
+    #[test]
+    fn deployment() {
+        let d: Value =
+            serde_json::from_reader(File::open("test/deployment.json").expect("find file"))
+                .unwrap();
+        let tp = &event_kind(None, &d, &slog::Logger::root(slog::Discard, o!()));
+        assert_eq!(tp, "deployment");
+
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("Administrator deployed 279484c0 to production"));
+        assert!(s.ends_with(": success"));
+    }
+
+    #[test]
+    fn feature_flag() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/feature_flag.json").expect("find file"))
+                .unwrap();
+        let tp = &event_kind(None, &d, &slog::Logger::root(slog::Discard, o!()));
+        assert_eq!(tp, "feature_flag");
+
+        let s = dispatch(
+            tp,
+            d.clone(),
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("Feature flag \"beta\" enabled on Gitlab Test"));
+
+        d["object_attributes"]["active"] = Value::Bool(false);
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("Feature flag \"beta\" disabled on Gitlab Test"));
+    }
+
+    #[test]
+    fn member() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/member.json").expect("find file")).unwrap();
+        let tp = &event_kind(None, &d, &slog::Logger::root(slog::Discard, o!()));
+        assert_eq!(tp, "member");
+
+        let s = dispatch(
+            tp,
+            d.clone(),
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("alice added as Developer to gitlab-org/gitlab-test"));
+
+        d["action"] = Value::from("removed");
+        let s = dispatch(
+            tp,
+            d.clone(),
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.unwrap().contains("alice removed as Developer from"));
+
+        let s = dispatch(
+            "access_request",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.unwrap().contains("alice requested Developer access to"));
+    }
+
+    #[test]
+    fn system_project_create() {
+        let tp = "project_create";
+        let d = serde_json::from_reader(
+            File::open("test/system_project_create.json").expect("find file"),
+        )
+        .unwrap();
+
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("John Smith created project jsmith/storecloud"));
+    }
+
+    #[test]
+    fn system_user_add_to_team() {
+        let tp = "user_add_to_team";
+        let d = serde_json::from_reader(
+            File::open("test/system_user_add_to_team.json").expect("find file"),
+        )
+        .unwrap();
+
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+        assert!(s.is_ok());
+        let s = s.unwrap();
+        assert!(s.contains("John Smith added to project jsmith/storecloud as Maintainer"));
+    }
+
+    #[test]
+    fn event_kind_from_header() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+
+        let data = json!({ "object_kind": "push" });
+        assert_eq!(event_kind(None, &data, &logger), "push");
+        assert_eq!(event_kind(Some("Push Hook"), &data, &logger), "push");
+
+        let data = json!({});
+        assert_eq!(event_kind(Some("Note Hook"), &data, &logger), "note");
+        assert_eq!(
+            event_kind(Some("Unknown Hook"), &data, &logger),
+            "no object kind"
+        );
+        assert_eq!(event_kind(None, &data, &logger), "no object kind");
+
+        let data = json!({ "event_name": "project_create" });
+        assert_eq!(
+            event_kind(Some("System Hook"), &data, &logger),
+            "project_create"
+        );
+    }
+
+    #[test]
+    fn draft_merge_request() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/merge_request.json").expect("find file"))
+                .unwrap();
+        assert!(!is_draft_merge_request("merge_request", &d));
+
+        d["object_attributes"]["title"] = Value::from("Draft: MS-Viewport");
+        assert!(is_draft_merge_request("merge_request", &d));
+        assert!(!is_draft_merge_request("issue", &d));
+
+        d["object_attributes"]["title"] = Value::from("MS-Viewport");
+        d["object_attributes"]["work_in_progress"] = Value::from(true);
+        assert!(is_draft_merge_request("merge_request", &d));
+    }
+
+    #[test]
+    fn head_commit() {
+        let mut d: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+        assert_eq!(head_commit_message("push", &d), Some("fixed readme"));
+        assert_eq!(head_commit_message("tag_push", &d), None);
+
+        d["after"] = d["commits"][0]["id"].clone();
+        assert_eq!(
+            head_commit_message("push", &d),
+            Some("Update Catalan translation to e38cb41.")
+        );
+
+        d["commits"] = Value::Array(Vec::new());
+        assert_eq!(head_commit_message("push", &d), None);
+    }
+
     #[test]
     fn merge_request() {
         let tp = "merge_request";
         let d = serde_json::from_reader(File::open("test/merge_request.json").expect("find file"))
             .unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
 
         assert!(s.contains("opened merge request"));
+        assert!(!s.contains("blocked"));
+        assert!(!s.contains("auto-merge"));
+    }
+
+    #[test]
+    fn merge_request_approval() {
+        let format = |action: &str| {
+            let mut d: Value = serde_json::from_reader(
+                File::open("test/merge_request_approved.json").expect("find file"),
+            )
+            .unwrap();
+            d["object_attributes"]["action"] = Value::from(action);
+            let cfg = FormatConfig {
+                show_urls: false,
+                ..FormatConfig::default()
+            };
+            dispatch(
+                "merge_request",
+                d,
+                &cfg,
+                &slog::Logger::root(slog::Discard, o!()),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            format("approved"),
+            "✅ Administrator approved merge request !42 \"MS-Viewport\" on Gitlab Test"
+        );
+        assert_eq!(format("approval"), format("approved"));
+        assert_eq!(
+            format("unapproved"),
+            "🚓 Administrator unapproved merge request !42 \"MS-Viewport\" on Gitlab Test"
+        );
+    }
+
+    #[test]
+    fn merge_request_merge_status() {
+        let format = |file: &str| {
+            let d = serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            dispatch(
+                "merge_request",
+                d,
+                &FormatConfig::default(),
+                &slog::Logger::root(slog::Discard, o!()),
+            )
+            .unwrap()
+        };
+
+        assert!(
+            format("test/merge_request_unmergeable.json").contains(") (blocked: unmergeable) on ")
+        );
+        assert!(format("test/merge_request_auto_merge.json").contains("(auto-merge on green)"));
+    }
+
+    #[test]
+    fn state_transitions() {
+        let format = |file: &str, kind: &str, action: &str, state: &str| {
+            let mut d: Value =
+                serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            d["object_attributes"]["action"] = Value::from(action);
+            d["object_attributes"]["state"] = Value::from(state);
+            let cfg = FormatConfig {
+                show_urls: false,
+                show_repo: false,
+                ..FormatConfig::default()
+            };
+            dispatch(kind, d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert_eq!(
+            format("test/issue.json", "issue", "update", "closed"),
+            "🐛 Administrator updated issue \"New API: create/update/delete file\" → closed"
+        );
+        assert_eq!(
+            format("test/issue.json", "issue", "close", "closed"),
+            "🐛 Administrator closed issue \"New API: create/update/delete file\""
+        );
+        assert_eq!(
+            format("test/issue.json", "issue", "reopen", "opened"),
+            "🐛 Administrator reopened issue \"New API: create/update/delete file\""
+        );
+        assert!(format(
+            "test/merge_request.json",
+            "merge_request",
+            "update",
+            "locked"
+        )
+        .contains("updated merge request \"MS-Viewport\" → locked"));
+        assert!(format(
+            "test/merge_request.json",
+            "merge_request",
+            "merge",
+            "merged"
+        )
+        .ends_with("merged merge request \"MS-Viewport\""));
+        assert!(format(
+            "test/merge_request.json",
+            "merge_request",
+            "approved",
+            "opened"
+        )
+        .ends_with("approved merge request \"MS-Viewport\""));
+    }
+
+    #[test]
+    fn merge_note_only_while_open() {
+        let mut d: Value = serde_json::from_reader(
+            File::open("test/merge_request_unmergeable.json").expect("find file"),
+        )
+        .unwrap();
+        d["object_attributes"]["action"] = Value::from("merge");
+        let mr = MergeRequest::deserialize(&d["object_attributes"]).unwrap();
+        assert_eq!(mr.merge_note(), None);
     }
 
     #[test]
@@ -483,7 +1754,12 @@ mod tests {
         let tp = "wiki_page";
         let d = serde_json::from_reader(File::open("test/wiki.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
 
@@ -496,19 +1772,211 @@ mod tests {
         let d =
             serde_json::from_reader(File::open("test/pipeline.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
 
         assert!(s.contains("Pipeline success"));
     }
 
+    #[test]
+    fn relative_time() {
+        let format = |file: &str, kind: &str, change: &dyn Fn(&mut Value)| {
+            let mut d: Value =
+                serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            change(&mut d);
+            // 2016-08-12 15:30:00 UTC
+            let cfg = FormatConfig {
+                relative_time: true,
+                ..FormatConfig::default()
+            }
+            .at(&MockClock::new(
+                UNIX_EPOCH + Duration::from_secs(1_471_015_800),
+            ));
+            dispatch(kind, d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert!(format("test/pipeline.json", "pipeline", &|_| ())
+            .contains("Pipeline success in 63 seconds, 3m ago for"));
+        assert!(format("test/build.json", "build", &|d| {
+            d["build_finished_at"] = Value::from("2016-08-12 13:30:00 UTC")
+        })
+        .contains("created 2h ago on"));
+        assert!(format("test/push.json", "push", &|d| {
+            d["commits"][0]["timestamp"] = Value::from("2016-08-12T17:25:00+02:00")
+        })
+        .contains(" 5m ago: "));
+    }
+
+    #[test]
+    fn line_template() {
+        let format = |kind: &str, file: &str, template: &str| {
+            let d = serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            let cfg = FormatConfig {
+                compact: true,
+                line_template: Some(String::from(template)),
+                ..FormatConfig::default()
+            };
+            dispatch(kind, d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert_eq!(
+            format("push", "test/push.json", "{emoji} [{project}] {body}"),
+            "🌋 [mike/diaspora] John Smith pushed 4 commits on the default branch"
+        );
+        assert_eq!(
+            format("issue", "test/issue.json", "{kind}: {body} {emoji}"),
+            "issue: Administrator opened issue \"New API: create/update/delete...\" 🐛"
+        );
+    }
+
+    #[test]
+    fn compact() {
+        let format = |kind: &str, file: &str, compact: bool| {
+            let d = serde_json::from_reader(File::open(file).expect("find file")).unwrap();
+            let cfg = FormatConfig {
+                compact,
+                ..FormatConfig::default()
+            };
+            dispatch(kind, d, &cfg, &slog::Logger::root(slog::Discard, o!())).unwrap()
+        };
+
+        assert_eq!(
+            format("push", "test/push.json", false),
+            "🌋 John Smith pushed 4 commits to Diaspora (http://example.com/mike/diaspora) \
+             on the default branch http://example.com/mike/diaspora/-/compare/\
+             95790bf891e76fee5e1747ab589903a6a1f80f22...da1560886d4f094c3e6c9ef40349f7d38b5d27d7\n  \
+             b6568db by Jordi Mallach on 2011-12-12: Update Catalan translation to e38cb41.\n  \
+             da15608 by GitLab dev user on 2012-01-03: fixed readme"
+        );
+        assert_eq!(
+            format("push", "test/push.json", true),
+            "🌋 John Smith pushed 4 commits on the default branch"
+        );
+
+        assert_eq!(
+            format("issue", "test/issue.json", false),
+            "🐛 Administrator opened issue \"New API: create/update/delete file\" \
+             (http://example.com/diaspora/issues/23) on Gitlab Test \
+             (http://example.com/gitlabhq/gitlab-test)"
+        );
+        assert_eq!(
+            format("issue", "test/issue.json", true),
+            "🐛 Administrator opened issue \"New API: create/update/delete...\""
+        );
+
+        assert_eq!(
+            format("pipeline", "test/pipeline.json", false),
+            "👷 Pipeline success in 63 seconds for bcbb5ec \"test\" by User \
+             (http://example.com/gitlab-org/gitlab-test/commit/bcbb5ec396a2c0f828686f14fac9b80b780504f2) \
+             on Gitlab Test (http://192.168.64.1:3005/gitlab-org/gitlab-test)"
+        );
+        assert_eq!(
+            format("pipeline", "test/pipeline.json", true),
+            "👷 Pipeline success in 63 seconds for bcbb5ec \"test\" by User"
+        );
+    }
+
+    #[test]
+    fn pipeline_commit() {
+        let format = |change: &dyn Fn(&mut Value)| {
+            let mut d: Value =
+                serde_json::from_reader(File::open("test/pipeline.json").expect("find file"))
+                    .unwrap();
+            change(&mut d);
+            let cfg = FormatConfig {
+                show_urls: false,
+                ..FormatConfig::default()
+            };
+            dispatch(
+                "pipeline",
+                d,
+                &cfg,
+                &slog::Logger::root(slog::Discard, o!()),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            format(&|_| ()),
+            "👷 Pipeline success in 63 seconds for bcbb5ec \"test\" by User on Gitlab Test"
+        );
+        assert!(format(&|d| d["commit"]["title"] = Value::from("Fix bug"))
+            .contains(" for bcbb5ec \"Fix bug\" by User on "));
+        // older payloads without an author
+        assert!(
+            format(&|d| d["commit"]["author"] = Value::Null).contains(" for bcbb5ec \"test\" on ")
+        );
+    }
+
+    #[test]
+    fn pipeline_source() {
+        let format = |source: &str| {
+            let mut d: Value =
+                serde_json::from_reader(File::open("test/pipeline.json").expect("find file"))
+                    .unwrap();
+            d["object_attributes"]["source"] = Value::from(source);
+            dispatch(
+                "pipeline",
+                d,
+                &FormatConfig::default(),
+                &slog::Logger::root(slog::Discard, o!()),
+            )
+            .unwrap()
+        };
+
+        assert!(format("schedule").contains("Pipeline success (scheduled) in 63 seconds"));
+        assert!(format("merge_request_event").contains("Pipeline success (merge request) in"));
+        assert!(format("parent_pipeline").contains("Pipeline success (parent pipeline) in"));
+        assert!(format("push").contains("Pipeline success in 63 seconds"));
+    }
+
+    #[test]
+    fn unknown_kind() {
+        let d = serde_json::json!({ "object_kind": "emoji" });
+
+        match dispatch(
+            "emoji",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        ) {
+            Err(Error::UnknownEvent(kind)) => assert_eq!(kind, "emoji"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_payload() {
+        let d = serde_json::json!({ "object_kind": "push" });
+
+        match dispatch(
+            "push",
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        ) {
+            Err(Error::Parse(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
     #[test]
     fn build() {
         let tp = "build";
         let d = serde_json::from_reader(File::open("test/build.json").expect("find file")).unwrap();
 
-        let s = dispatch(tp, d, &slog::Logger::root(slog::Discard, o!()));
+        let s = dispatch(
+            tp,
+            d,
+            &FormatConfig::default(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
         assert!(s.is_ok());
         let s = s.unwrap();
 
@@ -0,0 +1,72 @@
+/// Whether `text` matches `pattern`, where `*` stands for any number
+/// of characters and `?` for exactly one.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was and the text it matched up to, to go back to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the `*` take one more character
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p = sp + 1;
+                    t = st + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `pattern` has any wildcards in it.
+pub fn is_pattern(pattern: &str) -> bool {
+    pattern.contains(|c| c == '*' || c == '?')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact() {
+        assert!(matches("alice", "alice"));
+        assert!(!matches("alice", "alice2"));
+        assert!(!matches("alice", "alic"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*-bot", "deploy-bot"));
+        assert!(!matches("*-bot", "developer"));
+        assert!(!matches("*-bot", "deploy-bot2"));
+        assert!(matches("*", ""));
+        assert!(matches("group/*", "group/app"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(!matches("a*b*c", "axxbyy"));
+        assert!(matches("bot?", "bot1"));
+        assert!(!matches("bot?", "bot"));
+        assert!(matches("🦝?", "🦝!"));
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(is_pattern("*-bot"));
+        assert!(is_pattern("bot?"));
+        assert!(!is_pattern("alice"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::notification::Notification;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Entry {
+    pub timestamp: u64,
+    pub kind: String,
+    pub message: String,
+    /// What was sent, so that replays are routed like the original.
+    #[serde(skip)]
+    pub notification: Notification,
+}
+
+/// The last `capacity` formatted events, oldest first.
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, notification: &Notification) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            kind: notification.kind.clone(),
+            message: notification.message.clone(),
+            notification: notification.clone(),
+        });
+    }
+
+    /// At most `limit` entries, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<&Entry> {
+        self.entries.iter().rev().take(limit).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest() {
+        let notification = |kind: &str, message: &str| Notification {
+            kind: kind.to_owned(),
+            message: message.to_owned(),
+            ..Notification::default()
+        };
+        let mut history = History::new(2);
+        history.push(&notification("push", "one"));
+        history.push(&notification("push", "two"));
+        history.push(&notification("issue", "three"));
+
+        let recent = history
+            .recent(10)
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(recent, vec!["three", "two"]);
+        assert_eq!(history.recent(1)[0].notification.kind, "issue");
+        assert_eq!(history.recent(1).len(), 1);
+    }
+}
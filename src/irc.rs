@@ -1,57 +1,825 @@
 use irc::client::{self, ext::ClientExt};
-use std::{collections::HashMap, sync::mpsc, thread};
+use irc::proto::{command::Command, message::Message, response::Response};
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, RwLock, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+use crate::digest::{self, Digest};
+use crate::error::Error;
+use crate::filter::ChannelFilter;
+use crate::format::FormatConfig;
+use crate::notification::Notification;
+use crate::ratelimit::{Admission, Limiter, RateLimit};
+use crate::routing::RouteTable;
+use crate::secret::{self, Secret};
+use crate::stats::DeliveryCounters;
 
 pub use irc::client::Client;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 struct IrcConfig {
     nickname: String,
-    nick_password: String,
+    #[serde(default)]
+    nick_password: Secret,
+    /// File to read the nick password from instead.
+    #[serde(default)]
+    nick_password_file: Option<PathBuf>,
     server: String,
     port: u16,
-    channels: Vec<String>,
+    /// CA certificate to trust the server's certificate by, in DER format.
+    #[serde(default)]
+    tls_cert_path: Option<PathBuf>,
+    channels: Vec<Channel>,
+    #[serde(default)]
+    identify_command: Option<String>,
+    /// The user name shown in WHOIS, the nickname if not set.
+    #[serde(default)]
+    username: Option<String>,
+    /// The real name shown in WHOIS.
+    #[serde(default)]
+    realname: Option<String>,
+    #[serde(default)]
+    rate_limit: Option<RateLimit>,
+    /// Caps the total number of messages across all channels.
+    #[serde(default)]
+    global_rate_limit: Option<RateLimit>,
+    #[serde(default = "default_send_retries")]
+    send_retries: u32,
+    #[serde(default = "default_send_retry_delay_ms")]
+    send_retry_delay_ms: u64,
+    /// Reconnect when sending fails even after retrying.
+    #[serde(default = "default_reconnect_on_send_failure")]
+    reconnect_on_send_failure: bool,
+    /// Channels to send deployments to, by environment.
+    #[serde(default)]
+    deployment_routes: HashMap<String, Vec<String>>,
+    /// Channels to send events to, by a key built from the event.
+    #[serde(default)]
+    routing: Option<RouteTable>,
+    #[serde(default)]
+    push_mode: PushMode,
+    /// Time between joining two channels, joining all at once if 0.
+    #[serde(default)]
+    join_delay_ms: u64,
+    /// Time to collect messages for a channel into a digest, sending right away if 0.
+    #[serde(default)]
+    digest_window_ms: u64,
+    /// Connected through a bouncer, which keeps the channels joined for us.
+    #[serde(default)]
+    bouncer_mode: bool,
+    /// Send messages as CTCP ACTIONs, like `/me` does.
+    #[serde(default)]
+    use_action: bool,
 }
 
-pub struct RealIrcWriter {
-    client: client::IrcClient,
+/// How much of a push to send.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PushMode {
+    /// Only the line saying who pushed where.
+    Summary,
+    /// That line and then one for each of the first few commits.
+    PerCommit,
 }
 
-impl RealIrcWriter {
-    pub fn new(client: client::IrcClient) -> Self {
-        RealIrcWriter { client }
+impl Default for PushMode {
+    fn default() -> Self {
+        PushMode::PerCommit
     }
 }
 
-pub trait IrcWriter {
-    fn write(&mut self, message: &str) -> Result<(), String>;
+/// A channel to join, either just its name (with an optional `:key`)
+/// or a table that also says what to send there.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Channel {
+    Plain(String),
+    Filtered {
+        name: String,
+        #[serde(default)]
+        filters: ChannelFilter,
+    },
 }
 
-impl IrcWriter for RealIrcWriter {
-    fn write(&mut self, message: &str) -> Result<(), String> {
-        if let Some(channels) = self.client.list_channels() {
-            for chan in channels {
-                if let Err(e) = self
-                    .client
-                    .send_privmsg(&chan, message)
-                    .map_err(|e| format!("failed to send IRC message to channel {}: {}", &chan, e))
-                {
-                    return Err(e);
+impl Channel {
+    /// The name of the channel, including the key if there is one.
+    fn spec(&self) -> &str {
+        match self {
+            Channel::Plain(name) | Channel::Filtered { name, .. } => name,
+        }
+    }
+
+    /// Adds the `#` people tend to forget, returning the fixed name if it was missing.
+    fn normalize(&mut self) -> Option<String> {
+        let name = match self {
+            Channel::Plain(name) | Channel::Filtered { name, .. } => name,
+        };
+
+        if name.is_empty() || name.starts_with(|c: char| c == '#' || c == '&' || c == ':') {
+            None
+        } else {
+            name.insert(0, '#');
+            Some(name.split(':').next().unwrap_or_default().to_owned())
+        }
+    }
+
+    /// The name of the channel without its key, which is not case sensitive.
+    fn name(&self) -> String {
+        self.spec()
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    fn has_key(&self) -> bool {
+        self.spec().contains(':')
+    }
+}
+
+/// Drops channels listed more than once, keeping the first entry unless a
+/// later one has a key, and returns the names of those that were.
+fn dedup_channels(channels: &mut Vec<Channel>) -> Vec<String> {
+    let mut kept: Vec<Channel> = Vec::with_capacity(channels.len());
+    let mut duplicates = Vec::new();
+    for channel in channels.drain(..) {
+        let name = channel.name();
+        match kept.iter().position(|c| c.name() == name) {
+            Some(i) => {
+                if channel.has_key() && !kept[i].has_key() {
+                    kept[i] = channel;
+                }
+                if !duplicates.contains(&name) {
+                    duplicates.push(name);
+                }
+            }
+            None => kept.push(channel),
+        }
+    }
+    *channels = kept;
+    duplicates
+}
+
+/// Whether `name` could be a channel, so typos are caught at startup
+/// rather than leaving the bot silently in no channel at all.
+fn valid_channel(name: &str) -> bool {
+    name.len() > 1
+        && (name.starts_with('#') || name.starts_with('&'))
+        && !name.contains(|c: char| c == ' ' || c == ',' || c == '\x07')
+}
+
+impl IrcConfig {
+    /// Checks that every channel has a proper name, listing the ones that do not.
+    fn validate(&self) -> Result<(), Error> {
+        // without any channels, every message would silently go nowhere
+        if self.channels.is_empty() {
+            return Err(Error::Config(format!("no channels on {}", self.server)));
+        }
+
+        let invalid = self
+            .channels
+            .iter()
+            .map(Channel::spec)
+            .filter(|spec| !valid_channel(spec.split(':').next().unwrap_or_default()))
+            .map(|spec| format!("\"{}\"", spec))
+            .collect::<Vec<_>>();
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Config(format!(
+                "invalid channel names on {}: {}",
+                self.server,
+                invalid.join(", ")
+            )))
+        }
+    }
+
+    fn channel_specs(&self) -> Vec<String> {
+        self.channels.iter().map(|c| c.spec().to_owned()).collect()
+    }
+
+    /// Filters of the channels that have them, by lowercased channel name.
+    fn channel_filters(&self) -> HashMap<String, ChannelFilter> {
+        self.channels
+            .iter()
+            .filter_map(|c| match c {
+                Channel::Filtered { filters, .. } => Some((c.name(), filters.clone())),
+                Channel::Plain(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn default_send_retries() -> u32 {
+    2
+}
+
+fn default_send_retry_delay_ms() -> u64 {
+    500
+}
+
+fn default_reconnect_on_send_failure() -> bool {
+    true
+}
+
+/// Least time between two reconnects caused by failing sends, so
+/// that a flood of messages to a broken connection is not a flood
+/// of reconnects as well.
+const SEND_FAILURE_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Used to identify with NickServ when reclaiming our nick
+/// and no `identify_command` is configured.
+const DEFAULT_IDENTIFY_COMMAND: &str = "PRIVMSG NickServ :IDENTIFY {password}";
+
+/// How often queued messages are checked for available send capacity.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longest line servers accept, including the trailing CRLF.
+const MAX_LINE_BYTES: usize = 512;
+
+/// Room left for the `:nick!user@host ` source that the
+/// server puts in front of our messages when relaying them.
+const SOURCE_RESERVE: usize = 100;
+
+/// The parts of an IRC client that the writer uses.
+pub trait Sender {
+    fn channels(&self) -> Option<Vec<String>>;
+    fn privmsg(&self, target: &str, message: &str) -> Result<(), Error>;
+    fn reconnect(&self) -> Result<(), Error>;
+}
+
+impl Sender for client::IrcClient {
+    fn channels(&self) -> Option<Vec<String>> {
+        self.list_channels()
+    }
+
+    fn privmsg(&self, target: &str, message: &str) -> Result<(), Error> {
+        self.send_privmsg(target, message)
+            .map_err(|e| Error::IrcSend(e.to_string()))
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+        self.send_quit("Reconnecting")
+            .map_err(|e| Error::IrcSend(e.to_string()))
+    }
+}
+
+/// The current IRC client, shared between the writer and the
+/// IRC thread which replaces it every time it reconnects.
+#[derive(Clone, Default)]
+pub struct Connection {
+    client: Arc<RwLock<Option<client::IrcClient>>>,
+    reconnect_requested: Arc<AtomicBool>,
+}
+
+impl Connection {
+    fn connected(&self, client: client::IrcClient) {
+        if let Ok(mut c) = self.client.write() {
+            *c = Some(client);
+        }
+    }
+
+    /// Forgets the current client, returns true if there was one.
+    fn disconnected(&self) -> bool {
+        self.client
+            .write()
+            .map(|mut c| c.take().is_some())
+            .unwrap_or(false)
+    }
+
+    fn take_reconnect_request(&self) -> bool {
+        self.reconnect_requested.swap(false, Ordering::SeqCst)
+    }
+
+    fn with_client<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&client::IrcClient) -> Result<T, Error>,
+    {
+        let c = self.client.read().map_err(|_| Error::Lock("irc client"))?;
+        match *c {
+            Some(ref client) => f(client),
+            None => Err(Error::NotConnected),
+        }
+    }
+}
+
+impl Sender for Connection {
+    fn channels(&self) -> Option<Vec<String>> {
+        self.with_client(|c| Ok(c.channels())).ok().and_then(|c| c)
+    }
+
+    fn privmsg(&self, target: &str, message: &str) -> Result<(), Error> {
+        self.with_client(|c| c.privmsg(target, message))
+    }
+
+    /// Signals the IRC thread to reconnect and closes the current
+    /// connection, which makes the event loop return.
+    fn reconnect(&self) -> Result<(), Error> {
+        self.reconnect_requested.store(true, Ordering::SeqCst);
+        self.with_client(|c| c.reconnect())
+    }
+}
+
+/// Counts the messages sent to each channel and the attempts that failed.
+#[derive(Clone)]
+struct Counted<C> {
+    inner: C,
+    deliveries: Arc<Mutex<DeliveryCounters>>,
+}
+
+impl<C: Sender> Sender for Counted<C> {
+    fn channels(&self) -> Option<Vec<String>> {
+        self.inner.channels()
+    }
+
+    fn privmsg(&self, target: &str, message: &str) -> Result<(), Error> {
+        let result = self.inner.privmsg(target, message);
+        if let Ok(mut d) = self.deliveries.lock() {
+            d.record(target, result.is_ok());
+        }
+        result
+    }
+
+    fn reconnect(&self) -> Result<(), Error> {
+        self.inner.reconnect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Retry {
+    attempts: u32,
+    delay: Duration,
+}
+
+fn send_with_retry<C: Sender>(
+    client: &C,
+    chan: &str,
+    message: &str,
+    retry: Retry,
+) -> Result<(), Error> {
+    let mut attempt = 0;
+    loop {
+        match client.privmsg(chan, message) {
+            Ok(()) => return Ok(()),
+            Err(_) if attempt < retry.attempts => {
+                attempt += 1;
+                thread::sleep(retry.delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Bytes left for the text of a message to `chan`.
+fn line_budget(chan: &str) -> usize {
+    MAX_LINE_BYTES.saturating_sub(SOURCE_RESERVE + "PRIVMSG  :\r\n".len() + chan.len())
+}
+
+/// Splits `text` into parts of at most `budget` bytes, never in the middle of a character.
+fn split_at_budget(text: &str, budget: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while rest.len() > budget {
+        let mut end = budget;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // not even a single character fits, so send one anyway
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+    }
+    parts.push(rest);
+    parts
+}
+
+/// Wraps `message` in the configured prefix and suffix, splitting the message
+/// itself into as many lines as it takes for each to fit in `budget` bytes.
+fn decorate(message: &str, format: &FormatConfig, budget: usize) -> Vec<String> {
+    let room = budget.saturating_sub(format.prefix.len() + format.suffix.len());
+    split_at_budget(message, room)
+        .into_iter()
+        .map(|part| format!("{}{}{}", format.prefix, part, format.suffix))
+        .collect()
+}
+
+pub struct RealIrcWriter<C = Connection> {
+    client: Counted<C>,
+    deliveries: Arc<Mutex<DeliveryCounters>>,
+    limiter: Option<Arc<Mutex<Limiter>>>,
+    digest: Option<Arc<Mutex<Digest>>>,
+    retry: Retry,
+    format: FormatConfig,
+    deployment_routes: HashMap<String, Vec<String>>,
+    routing: Option<RouteTable>,
+    channel_filters: HashMap<String, ChannelFilter>,
+    push_mode: PushMode,
+    clock: Arc<Clock + Send + Sync>,
+    reconnect_on_send_failure: bool,
+    last_failure_reconnect: Mutex<Option<Instant>>,
+    log: slog::Logger,
+}
+
+impl<C: Sender + Clone + Send + 'static> RealIrcWriter<C> {
+    fn new(
+        client: C,
+        cfg: &IrcConfig,
+        format: FormatConfig,
+        clock: Arc<Clock + Send + Sync>,
+        logger: &slog::Logger,
+    ) -> Self {
+        let format = if cfg.use_action {
+            action_format(format)
+        } else {
+            format
+        };
+        let retry = Retry {
+            attempts: cfg.send_retries,
+            delay: Duration::from_millis(cfg.send_retry_delay_ms),
+        };
+        let deliveries = Arc::new(Mutex::new(DeliveryCounters::default()));
+        let client = Counted {
+            inner: client,
+            deliveries: deliveries.clone(),
+        };
+        let limiter = match (cfg.rate_limit.clone(), cfg.global_rate_limit.clone()) {
+            (Some(rl), Some(global)) => Some(Limiter::new(rl).with_global(global)),
+            (Some(rl), None) => Some(Limiter::new(rl)),
+            (None, Some(global)) => Some(Limiter::global(global)),
+            (None, None) => None,
+        }
+        .map(|l| Arc::new(Mutex::new(l)));
+
+        if let Some(ref l) = limiter {
+            let weak = Arc::downgrade(l);
+            let client = client.clone();
+            let clock = clock.clone();
+            let log = logger.new(o!());
+            thread::spawn(move || flush_queued(&weak, &client, retry, &*clock, &log));
+        }
+
+        let digest = match cfg.digest_window_ms {
+            0 => None,
+            ms => Some(Arc::new(Mutex::new(Digest::new(Duration::from_millis(ms))))),
+        };
+        if let Some(ref d) = digest {
+            let weak = Arc::downgrade(d);
+            let client = client.clone();
+            let limiter = limiter.clone();
+            let format = format.clone();
+            let clock = clock.clone();
+            let log = logger.new(o!());
+            thread::spawn(move || {
+                flush_digests(&weak, &client, &limiter, &format, retry, &*clock, &log)
+            });
+        }
+
+        RealIrcWriter {
+            client,
+            deliveries,
+            limiter,
+            digest,
+            retry,
+            format,
+            deployment_routes: cfg.deployment_routes.clone(),
+            routing: cfg.routing.clone(),
+            channel_filters: cfg.channel_filters(),
+            push_mode: cfg.push_mode,
+            clock,
+            reconnect_on_send_failure: cfg.reconnect_on_send_failure,
+            last_failure_reconnect: Mutex::new(None),
+            log: logger.new(o!()),
+        }
+    }
+}
+
+/// Trickles out rate limited messages until the writer goes away.
+fn flush_queued<C: Sender>(
+    limiter: &Weak<Mutex<Limiter>>,
+    client: &C,
+    retry: Retry,
+    clock: &Clock,
+    log: &slog::Logger,
+) {
+    while let Some(l) = limiter.upgrade() {
+        let ready = match l.lock() {
+            Ok(mut guard) => guard.drain(clock.now()),
+            Err(_) => {
+                error!(log, "failed to obtain rate limiter lock");
+                return;
+            }
+        };
+
+        for (chan, message) in ready {
+            if let Err(e) = send_with_retry(client, &chan, &message, retry) {
+                error!(
+                    log,
+                    "failed to send queued message to channel {}: {}", chan, e
+                );
+            }
+        }
+
+        drop(l);
+        thread::sleep(FLUSH_INTERVAL);
+    }
+}
+
+/// Sends digests once their window has passed, until the writer goes away.
+fn flush_digests<C: Sender>(
+    digests: &Weak<Mutex<Digest>>,
+    client: &C,
+    limiter: &Option<Arc<Mutex<Limiter>>>,
+    format: &FormatConfig,
+    retry: Retry,
+    clock: &Clock,
+    log: &slog::Logger,
+) {
+    while let Some(d) = digests.upgrade() {
+        let due = match d.lock() {
+            Ok(mut guard) => guard.due(clock.now()),
+            Err(_) => {
+                error!(log, "failed to obtain digest lock");
+                return;
+            }
+        };
+        drop(d);
+
+        let decoration = format.prefix.len() + format.suffix.len();
+        for (chan, messages) in due {
+            let budget = line_budget(&chan).saturating_sub(decoration);
+            let message = digest::combine(&messages, budget);
+            if let Err(e) = send_lines(client, limiter, format, retry, clock, &chan, &message) {
+                error!(log, "failed to send digest to channel {}: {}", chan, e);
+            }
+        }
+
+        thread::sleep(FLUSH_INTERVAL);
+    }
+}
+
+/// Frames every line in a CTCP ACTION, the same as `send_action` does. Being
+/// part of the decoration, the framing survives splitting and rate limiting.
+fn action_format(format: FormatConfig) -> FormatConfig {
+    FormatConfig {
+        prefix: format!("\u{1}ACTION {}", format.prefix),
+        suffix: format!("{}\u{1}", format.suffix),
+        ..format
+    }
+}
+
+/// Sends every line of `message` to `chan`, through the rate limiter if there is one.
+fn send_lines<C: Sender>(
+    client: &C,
+    limiter: &Option<Arc<Mutex<Limiter>>>,
+    format: &FormatConfig,
+    retry: Retry,
+    clock: &Clock,
+    chan: &str,
+    message: &str,
+) -> Result<(), Error> {
+    // every line of the message is a message of its own on IRC,
+    // and lines too long for one are sent in parts
+    let lines = message
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .flat_map(|l| decorate(l, format, line_budget(chan)));
+    for line in lines {
+        if let Some(limiter) = limiter {
+            let admission = limiter
+                .lock()
+                .map_err(|_| Error::Lock("rate limiter"))?
+                .submit(chan, &line, clock.now());
+
+            match admission {
+                Admission::Send => (),
+                Admission::Queued => continue,
+                Admission::Dropped => return Err(Error::RateLimited(chan.to_owned())),
+            }
+        }
+
+        send_with_retry(client, chan, &line, retry)?;
+    }
+    Ok(())
+}
+
+impl<C: Sender> RealIrcWriter<C> {
+    /// Sends every line of `message` to each of `channels`.
+    fn deliver(&self, channels: &[String], message: &str) -> Result<(), Error> {
+        let mut errors = Vec::new();
+        for chan in channels {
+            // keep going, one broken channel should not stop the others
+            match send_lines(
+                &self.client,
+                &self.limiter,
+                &self.format,
+                self.retry,
+                &*self.clock,
+                chan,
+                message,
+            ) {
+                Ok(()) => (),
+                Err(e @ Error::Lock(_)) => return Err(e),
+                Err(e @ Error::RateLimited(_)) => errors.push(e.to_string()),
+                Err(e) => {
+                    if let Error::IrcSend(_) = e {
+                        self.reconnect_after_failure();
+                    }
+                    errors.push(format!("{}: {}", chan, e));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Delivery {
+                total: channels.len(),
+                errors,
+            })
+        }
+    }
+}
+
+impl<C: Sender> RealIrcWriter<C> {
+    /// Warns about lines of `message` too long for `channels` once decorated,
+    /// which get split, to make events of `kind` that regularly do so easy to spot.
+    fn check_length(&self, kind: &str, message: &str, channels: &[String]) {
+        let budget = channels
+            .iter()
+            .map(|c| line_budget(c))
+            .min()
+            .unwrap_or(MAX_LINE_BYTES);
+
+        let longest = message
+            .lines()
+            .map(|l| format!("{}{}{}", self.format.prefix, l, self.format.suffix).len())
+            .max()
+            .unwrap_or_default();
+        if longest > budget {
+            warn!(
+                self.log,
+                "{} message has a line of {} bytes, more than the {} that fit on IRC, splitting it",
+                kind,
+                longest,
+                budget
+            );
+        }
+    }
+
+    /// Gets a fresh connection once sending failed for good, unless
+    /// that was already done recently.
+    fn reconnect_after_failure(&self) {
+        if !self.reconnect_on_send_failure {
+            return;
+        }
+
+        let now = self.clock.now();
+        if let Ok(mut last) = self.last_failure_reconnect.lock() {
+            match *last {
+                Some(t) if now.duration_since(t) < SEND_FAILURE_RECONNECT_INTERVAL => (),
+                _ => {
+                    *last = Some(now);
+                    // the IRC thread logs if reconnecting fails
+                    let _ = self.client.reconnect();
                 }
             }
         }
+    }
+}
+
+/// What a writer knows about its connection to IRC.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    pub connected: bool,
+    /// The channels the client reports to have joined.
+    pub channels: Vec<String>,
+}
+
+pub trait IrcWriter {
+    fn write(&mut self, message: &str) -> Result<(), Error>;
+
+    /// Sends `notification` to the channels it is routed to, which
+    /// unless a writer knows better are all of them.
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        self.write(&notification.message)
+    }
 
+    /// Tears down the current connection and establishes a new one.
+    /// Writers without a connection have nothing to do.
+    fn reconnect(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    fn status(&self) -> Status {
+        Status::default()
+    }
+
+    /// Messages sent and failed attempts per channel, for `/metrics`.
+    fn deliveries(&self) -> DeliveryCounters {
+        DeliveryCounters::default()
+    }
+}
+
+impl<C: Sender> IrcWriter for RealIrcWriter<C> {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        // without a connection there are no channels, and nothing would be sent
+        let channels = self.client.channels().ok_or(Error::NotConnected)?;
+        self.deliver(&channels, message)
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        let routed = match (notification.kind.as_str(), &notification.environment) {
+            ("deployment", Some(env)) => self.deployment_routes.get(env),
+            _ => None,
+        }
+        .or_else(|| self.routing.as_ref().and_then(|r| r.lookup(notification)));
+
+        let channels = match routed {
+            Some(channels) => channels.clone(),
+            None => self.client.channels().ok_or(Error::NotConnected)?,
+        }
+        .into_iter()
+        .filter(|c| {
+            self.channel_filters
+                .get(&c.to_lowercase())
+                .map_or(true, |f| f.accepts(notification))
+        })
+        .collect::<Vec<_>>();
+
+        let message = match (notification.kind.as_str(), self.push_mode) {
+            ("push", PushMode::Summary) => notification.message.lines().next().unwrap_or_default(),
+            _ => &notification.message,
+        };
+
+        self.check_length(&notification.kind, message, &channels);
+        if let Some(ref digest) = self.digest {
+            let mut digest = digest.lock().map_err(|_| Error::Lock("digest"))?;
+            for chan in &channels {
+                digest.add(chan, message, self.clock.now());
+            }
+            return Ok(());
+        }
+        self.deliver(&channels, message)
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.client.reconnect()
+    }
+
+    fn status(&self) -> Status {
+        let channels = self.client.channels();
+        Status {
+            connected: channels.is_some(),
+            channels: channels.unwrap_or_default(),
+        }
+    }
+
+    fn deliveries(&self) -> DeliveryCounters {
+        self.deliveries
+            .lock()
+            .map(|d| d.clone())
+            .unwrap_or_default()
+    }
 }
 
 impl From<IrcConfig> for client::data::config::Config {
     fn from(cfg: IrcConfig) -> Self {
-        let (chans, keys) = split_channel_keys(&cfg.channels);
+        // paced joins are sent by ourselves, see `join_channels`,
+        // and a bouncer has joined the channels already
+        let (chans, keys) = if cfg.join_delay_ms > 0 || cfg.bouncer_mode {
+            (Vec::new(), HashMap::new())
+        } else {
+            split_channel_keys(&cfg.channel_specs())
+        };
         client::data::config::Config {
+            // fall back to a temporary nick so that registration succeeds
+            // while we reclaim the real one, see `reclaim_nick`
+            alt_nicks: Some(vec![format!("{}_", cfg.nickname)]),
             nickname: Some(cfg.nickname),
-            nick_password: Some(cfg.nick_password),
+            // with a custom identify command, we identify ourselves on welcome
+            nick_password: if cfg.identify_command.is_some() {
+                None
+            } else {
+                Some(cfg.nick_password.expose().to_owned())
+            },
+            username: cfg.username,
+            realname: cfg.realname,
             server: Some(cfg.server),
             port: Some(cfg.port),
+            cert_path: cfg.tls_cert_path.map(|p| p.to_string_lossy().into_owned()),
             channels: Some(chans),
             use_ssl: Some(true),
             channel_keys: Some(keys),
@@ -61,111 +829,1708 @@ impl From<IrcConfig> for client::data::config::Config {
 }
 
 fn split_channel_keys(channels: &[String]) -> (Vec<String>, HashMap<String, String>) {
+    // keys may contain colons themselves, so only split on the first one
+    let parts = channels
+        .iter()
+        .map(|c| {
+            let mut parts = c.splitn(2, ':');
+            (parts.next().unwrap_or_default(), parts.next())
+        })
+        .collect::<Vec<_>>();
+
     (
-        channels
-            .iter()
-            .map(|c| c.split(':').nth(0).unwrap().to_owned())
-            .collect(),
-        channels
+        parts.iter().map(|(chan, _)| (*chan).to_owned()).collect(),
+        parts
             .iter()
-            .filter_map(|c| {
-                let mut parts = c.split(':');
-                parts
-                    .nth(0)
-                    // the iterator does not rewind so need to use 0 again
-                    .and_then(|chan| match parts.nth(0) {
-                        Some(k) => Some((chan, k)),
-                        None => None,
-                    })
-                    .and_then(|(chan, key)| Some((chan.to_owned(), key.to_owned())))
-            })
+            .filter_map(|(chan, key)| key.map(|k| ((*chan).to_owned(), k.to_owned())))
             .collect(),
     )
 }
 
-pub fn init(config: &config::Config, logger: &slog::Logger) -> Result<client::IrcClient, String> {
-    let (tx, rx) = mpsc::channel();
-    let log = logger.new(o!());
-
-    let parsed: IrcConfig = config
-        .get("irc")
-        .map_err(|e| format!("failed to parse irc config: {}", e))?;
+/// Commands reclaiming our nickname if `msg` says that it is
+/// already in use, e.g. by a stale session after a dropped connection.
+fn reclaim_nick(msg: &Message, nickname: &str, password: &str) -> Vec<Command> {
+    match msg.command {
+        Command::Response(Response::ERR_NICKNAMEINUSE, ref args, _)
+            if args.iter().any(|a| a == nickname) =>
+        {
+            vec![
+                Command::PRIVMSG(
+                    String::from("NickServ"),
+                    format!("GHOST {} {}", nickname, password),
+                ),
+                Command::PRIVMSG(
+                    String::from("NickServ"),
+                    format!("REGAIN {} {}", nickname, password),
+                ),
+                Command::NICK(nickname.to_owned()),
+            ]
+        }
+        _ => Vec::new(),
+    }
+}
 
-    thread::spawn(move || -> Result<(), String> {
-        let mut reactor = client::reactor::IrcReactor::new()
-            .map_err(|e| format!("failed to create IRC reactor: {}", e))?;
-        let client = reactor
-            .prepare_client_and_connect(&parsed.into())
-            .map_err(|e| format!("failed to connect IRC client: {}", e))?;
+/// Says hello to the channel `msg` lists the names of, which is sent
+/// after joining it. Bouncers replay those lists on every reconnect,
+/// so there is no greeting in bouncer mode.
+fn greeting(msg: &Message, bouncer_mode: bool) -> Option<Command> {
+    match msg.command {
+        Command::Response(Response::RPL_NAMREPLY, ref args, _) if !bouncer_mode => args
+            .iter()
+            .find(|x| x.starts_with('#') || x.starts_with('&'))
+            .map(|c| {
+                Command::PRIVMSG(
+                    c.clone(),
+                    String::from("🦝 Hello! I am here to serve your Gitlab notifications!"),
+                )
+            }),
+        _ => None,
+    }
+}
 
-        client
-            .identify()
-            .map_err(|e| format!("failed to identify: {}", e))?;
+/// The channels greeted since joining them, as servers split the names
+/// of busy channels over many replies.
+#[derive(Default)]
+struct Greeted(HashSet<String>);
 
-        let msglog = log.new(o!());
-        reactor.register_client_with_handler(client.clone(), move |client, msg| {
-            let mut m = msg.to_string();
-            m.pop();
-            debug!(msglog, "{}", m);
+impl Greeted {
+    /// The greeting for the channel `msg` lists the names of,
+    /// unless it has been greeted already.
+    fn greeting(&mut self, msg: &Message, bouncer_mode: bool) -> Option<Command> {
+        match greeting(msg, bouncer_mode) {
+            Some(Command::PRIVMSG(ref channel, _)) if !self.0.insert(channel.to_lowercase()) => {
+                None
+            }
+            cmd => cmd,
+        }
+    }
 
-            match msg.command {
-                irc::proto::command::Command::Response(
-                    irc::proto::response::Response::RPL_WELCOME,
-                    _,
-                    _,
-                ) => {
-                    tx.send(client.clone()).unwrap();
+    /// Greets the channels again once `nickname` joins them anew, like after a kick.
+    fn joined(&mut self, msg: &Message, nickname: &str) {
+        if let Command::JOIN(ref channels, _, _) = msg.command {
+            if msg.source_nickname() == Some(nickname) {
+                for channel in channels.split(',') {
+                    self.0.remove(&channel.to_lowercase());
                 }
-                irc::proto::command::Command::Response(
-                    irc::proto::response::Response::RPL_NAMREPLY,
-                    ref args,
-                    _,
-                ) => {
-                    if let Some(c) = args.iter().find(|x| x.starts_with('#')) {
-                        client.send_privmsg(
-                            c,
-                            "🦝 Hello! I am here to serve your Gitlab notifications!",
-                        )?;
-                    }
-                }
-                _ => (),
             }
-            Ok(())
-        });
+        }
+    }
+}
 
-        info!(log, "starting IRC event loop");
-        reactor
-            .run()
-            .map_err(|e| format!("failed to start IRC event loop: {}", e))?;
+/// Fills in `{nick}` and `{password}` in an identify command template.
+fn render_identify_command(template: &str, nickname: &str, password: &str) -> String {
+    template
+        .replace("{nick}", nickname)
+        .replace("{password}", password)
+}
 
-        Ok(())
-    });
+/// Longest time to wait between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
 
-    let c = rx
-        .recv()
-        .map_err(|e| format!("failed to recieve irc client: {}", e))?;
-    info!(logger, "IRC client connected");
-    Ok(c)
+/// Exponential backoff between reconnection attempts, with full jitter
+/// so that bots dropped at the same time do not all come back at once.
+fn next_delay<R: Rng>(attempt: u32, cap: Duration, rng: &mut R) -> Duration {
+    let ceiling = cap.min(Duration::from_secs(1 << attempt.min(16)));
+    Duration::from_millis(rng.gen_range(0, ceiling.as_millis() as u64 + 1))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// When to join each of `count` channels, counted from the first join.
+fn join_offsets(count: usize, delay: Duration) -> Vec<Duration> {
+    (0..count as u32).map(|i| delay * i).collect()
+}
 
-    #[test]
-    fn test_split_channel_keys() {
-        let chans = vec![
-            String::from("#testchannel:password"),
-            String::from("#nopasschannel"),
-            String::from("#another-channel"),
-        ];
+/// Joins the channels one after the other, `delay` apart, so that
+/// servers limiting joins per second do not throttle or kick us.
+fn join_channels(
+    client: &client::IrcClient,
+    channels: &[String],
+    delay: Duration,
+    log: &slog::Logger,
+) {
+    let (chans, keys) = split_channel_keys(channels);
+    let client = client.clone();
+    let log = log.clone();
+    thread::spawn(move || {
+        let start = Instant::now();
+        for (chan, offset) in chans.iter().zip(join_offsets(chans.len(), delay)) {
+            let elapsed = start.elapsed();
+            if offset > elapsed {
+                thread::sleep(offset - elapsed);
+            }
+            let res = match keys.get(chan) {
+                Some(key) => client.send_join_with_keys(chan, key),
+                None => client.send_join(chan),
+            };
+            if let Err(e) = res {
+                warn!(log, "failed to join {}: {}", chan, e);
+            }
+        }
+    });
+}
 
-        let (channels, keys) = split_channel_keys(&chans);
+/// Connects to IRC and runs the event loop until the connection is closed.
+fn run(
+    cfg: &IrcConfig,
+    conn: &Connection,
+    ready: &mpsc::Sender<Result<(), Error>>,
+    log: &slog::Logger,
+) -> Result<(), Error> {
+    let mut reactor = client::reactor::IrcReactor::new()
+        .map_err(|e| Error::IrcConnect(format!("failed to create IRC reactor: {}", e)))?;
+    let client = reactor
+        .prepare_client_and_connect(&cfg.clone().into())
+        .map_err(|e| Error::IrcConnect(e.to_string()))?;
 
-        assert_eq!(channels.len(), 3);
-        assert_eq!(keys.len(), 1);
+    client
+        .identify()
+        .map_err(|e| Error::IrcConnect(format!("failed to identify: {}", e)))?;
 
-        assert!(keys.contains_key("#testchannel"));
-        assert_eq!(keys["#testchannel"], "password");
+    let msglog = log.new(o!());
+    let nickname = cfg.nickname.clone();
+    let nick_password = cfg.nick_password.expose().to_owned();
+    let identify_command = cfg.identify_command.clone();
+    let channels = cfg.channel_specs();
+    let join_delay = Duration::from_millis(cfg.join_delay_ms);
+    let bouncer_mode = cfg.bouncer_mode;
+    let greeted = Mutex::new(Greeted::default());
+    let conn = conn.clone();
+    let ready = ready.clone();
+    reactor.register_client_with_handler(client.clone(), move |client, msg| {
+        let mut m = msg.to_string();
+        m.pop();
+        debug!(msglog, "{}", m);
+
+        match msg.command {
+            Command::Response(Response::RPL_WELCOME, _, _) => {
+                if let Some(ref template) = identify_command {
+                    let identify = render_identify_command(template, &nickname, &nick_password);
+                    client.send(identify.parse::<Message>()?)?;
+                }
+                conn.connected(client.clone());
+
+                // nobody is listening anymore after the first connection
+                let _ = ready.send(Ok(()));
+            }
+            Command::Response(Response::RPL_ENDOFMOTD, _, _)
+            | Command::Response(Response::ERR_NOMOTD, _, _)
+                if join_delay > Duration::from_secs(0) && !bouncer_mode =>
+            {
+                join_channels(client, &channels, join_delay, &msglog);
+            }
+            Command::Response(Response::ERR_NICKNAMEINUSE, _, _) => {
+                warn!(
+                    msglog,
+                    "nickname {} is in use, trying to reclaim it", nickname
+                );
+                let cmds = reclaim_nick(&msg, &nickname, &nick_password);
+                if !cmds.is_empty() {
+                    for cmd in cmds {
+                        client.send(cmd)?;
+                    }
+
+                    let identify = render_identify_command(
+                        identify_command
+                            .as_ref()
+                            .map_or(DEFAULT_IDENTIFY_COMMAND, String::as_str),
+                        &nickname,
+                        &nick_password,
+                    );
+                    client.send(identify.parse::<Message>()?)?;
+                }
+            }
+            Command::JOIN(_, _, _) => {
+                if let Ok(mut g) = greeted.lock() {
+                    g.joined(&msg, &nickname);
+                }
+            }
+            Command::Response(Response::RPL_NAMREPLY, _, _) => {
+                let cmd = greeted
+                    .lock()
+                    .ok()
+                    .and_then(|mut g| g.greeting(&msg, bouncer_mode));
+                if let Some(cmd) = cmd {
+                    client.send(cmd)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    });
+
+    info!(log, "starting IRC event loop");
+    reactor
+        .run()
+        .map_err(|e| Error::IrcConnect(format!("IRC event loop failed: {}", e)))
+}
+
+/// The networks to connect to, either listed in `irc.networks`
+/// or a single one configured directly under `irc`.
+fn networks(config: &config::Config, logger: &slog::Logger) -> Result<Vec<IrcConfig>, Error> {
+    let mut networks = parse_networks(config)?;
+    for network in &mut networks {
+        for channel in network.channels.iter_mut().filter_map(Channel::normalize) {
+            warn!(
+                logger,
+                "channel names should start with #, using {} on {}", channel, network.server
+            );
+        }
+        // never log channel keys
+        for channel in dedup_channels(&mut network.channels) {
+            warn!(
+                logger,
+                "{} is listed more than once on {}, joining it once", channel, network.server
+            );
+        }
+        if let Some(ref path) = network.nick_password_file {
+            network.nick_password = Secret::new(secret::read(path)?);
+        }
+        if let Some(ref path) = network.tls_cert_path {
+            if !path.is_file() {
+                return Err(Error::Config(format!(
+                    "tls_cert_path {} for {} is not a file",
+                    path.display(),
+                    network.server
+                )));
+            }
+        }
+        if network.nick_password.expose().is_empty() {
+            return Err(Error::Config(format!(
+                "no nick_password or nick_password_file for {}",
+                network.server
+            )));
+        }
+        network.validate()?;
+    }
+    Ok(networks)
+}
+
+fn parse_networks(config: &config::Config) -> Result<Vec<IrcConfig>, Error> {
+    match config.get::<Vec<IrcConfig>>("irc.networks") {
+        Ok(networks) => {
+            if networks.is_empty() {
+                Err(Error::Config(String::from("irc.networks is empty")))
+            } else {
+                Ok(networks)
+            }
+        }
+        // the errors of the config crate may quote the values they failed on
+        Err(config::ConfigError::NotFound(_)) => config.get("irc").map(|n| vec![n]).map_err(|e| {
+            Error::Config(secret::redact(
+                &format!("failed to parse irc config: {}", e),
+                config,
+            ))
+        }),
+        Err(e) => Err(Error::Config(secret::redact(
+            &format!("failed to parse irc networks: {}", e),
+            config,
+        ))),
+    }
+}
+
+/// Checks the IRC settings without connecting anywhere.
+pub fn validate(config: &config::Config) -> Result<(), Error> {
+    networks(config, &slog::Logger::root(slog::Discard, o!())).map(|_| ())
+}
+
+/// Lines describing the configured networks for the startup summary.
+pub fn summary(config: &config::Config) -> Vec<String> {
+    // any warnings are logged when connecting
+    match networks(config, &slog::Logger::root(slog::Discard, o!())) {
+        Ok(networks) => networks
+            .iter()
+            .flat_map(|n| {
+                // never log channel keys
+                let (channels, _) = split_channel_keys(&n.channel_specs());
+                vec![
+                    format!("IRC server {}:{} as {}", n.server, n.port, n.nickname),
+                    format!("channels: {}", channels.join(", ")),
+                ]
+            })
+            .collect(),
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// Sends everything to several networks, each with its own routing.
+pub struct Networks<W> {
+    writers: Vec<W>,
+}
+
+impl<W: IrcWriter> IrcWriter for Networks<W> {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        let total = self.writers.len();
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.write(message).err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        network_errors(total, errors)
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        let total = self.writers.len();
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.notify(notification).err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        network_errors(total, errors)
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        let total = self.writers.len();
+        let errors = self
+            .writers
+            .iter_mut()
+            .filter_map(|w| w.reconnect().err())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        network_errors(total, errors)
+    }
+
+    /// Connected if all networks are, with the channels of all of them.
+    fn status(&self) -> Status {
+        self.writers.iter().map(IrcWriter::status).fold(
+            Status {
+                connected: true,
+                channels: Vec::new(),
+            },
+            |mut acc, s| {
+                acc.connected &= s.connected;
+                acc.channels.extend(s.channels);
+                acc
+            },
+        )
+    }
+
+    fn deliveries(&self) -> DeliveryCounters {
+        self.writers
+            .iter()
+            .fold(DeliveryCounters::default(), |mut acc, w| {
+                acc.merge(w.deliveries());
+                acc
+            })
+    }
+}
+
+fn network_errors(total: usize, errors: Vec<String>) -> Result<(), Error> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Networks { total, errors })
+    }
+}
+
+/// Connects to a single network, keeping the connection up from then on.
+fn connect(
+    parsed: IrcConfig,
+    format: FormatConfig,
+    clock: Arc<Clock + Send + Sync>,
+    logger: &slog::Logger,
+) -> Result<RealIrcWriter, Error> {
+    let (tx, rx) = mpsc::channel();
+    let log = logger.new(o!("server" => parsed.server.clone()));
+
+    let conn = Connection::default();
+    let writer = RealIrcWriter::new(conn.clone(), &parsed, format, clock, logger);
+
+    thread::spawn(move || {
+        let mut has_connected = false;
+        let mut attempt = 0;
+        loop {
+            let res = run(&parsed, &conn, &tx, &log);
+            let was_connected = conn.disconnected();
+
+            // fail startup if we never got a working connection
+            if !has_connected && !was_connected {
+                let _ = tx.send(res.and_then(|_| {
+                    Err(Error::IrcConnect(String::from(
+                        "connection closed before registration",
+                    )))
+                }));
+                return;
+            }
+            has_connected = true;
+
+            if let Err(e) = res {
+                error!(log, "{}", e);
+            }
+
+            if was_connected {
+                attempt = 0;
+            }
+
+            if conn.take_reconnect_request() {
+                info!(log, "reconnecting to IRC as requested");
+            } else {
+                let delay = next_delay(attempt, MAX_RECONNECT_DELAY, &mut rand::thread_rng());
+                attempt += 1;
+                warn!(log, "lost IRC connection, reconnecting in {:?}", delay);
+                thread::sleep(delay);
+            }
+        }
+    });
+
+    rx.recv()
+        .map_err(|e| Error::IrcConnect(format!("failed to recieve irc client: {}", e)))??;
+    info!(logger, "IRC client connected");
+    Ok(writer)
+}
+
+pub fn init(
+    config: &config::Config,
+    clock: Arc<Clock + Send + Sync>,
+    logger: &slog::Logger,
+) -> Result<Box<IrcWriter + Send>, Error> {
+    let mut networks = networks(config, logger)?;
+    let format = FormatConfig::from_config(config)?;
+
+    if networks.len() == 1 {
+        let network = networks.remove(0);
+        return Ok(Box::new(connect(network, format, clock, logger)?));
+    }
+
+    let writers = networks
+        .into_iter()
+        .map(|n| connect(n, format.clone(), clock.clone(), logger))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Box::new(Networks { writers }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+    use gotham::rustls::{self, ServerSession};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::time::UNIX_EPOCH;
+
+    fn test_config(channels: &[&str]) -> IrcConfig {
+        IrcConfig {
+            nickname: String::from("raccoon"),
+            nick_password: Secret::new(String::from("secret")),
+            nick_password_file: None,
+            identify_command: None,
+            username: None,
+            realname: None,
+            server: String::from("irc.example.com"),
+            port: 6697,
+            tls_cert_path: None,
+            channels: channels
+                .iter()
+                .map(|c| Channel::Plain(String::from(*c)))
+                .collect(),
+            rate_limit: None,
+            global_rate_limit: None,
+            send_retries: 2,
+            send_retry_delay_ms: 0,
+            reconnect_on_send_failure: true,
+            deployment_routes: HashMap::new(),
+            routing: None,
+            push_mode: PushMode::default(),
+            join_delay_ms: 0,
+            digest_window_ms: 0,
+            bouncer_mode: false,
+            use_action: false,
+        }
+    }
+
+    /// Client that fails the first `failures` sends to each channel.
+    #[derive(Clone)]
+    struct StubClient {
+        channels: Vec<String>,
+        failures: Arc<Mutex<HashMap<String, u32>>>,
+        sent: Arc<Mutex<Vec<(String, String)>>>,
+        reconnects: Arc<Mutex<u32>>,
+    }
+
+    impl StubClient {
+        fn new(channels: &[&str]) -> Self {
+            StubClient {
+                channels: channels.iter().map(|c| String::from(*c)).collect(),
+                failures: Arc::new(Mutex::new(HashMap::new())),
+                sent: Arc::new(Mutex::new(Vec::new())),
+                reconnects: Arc::new(Mutex::new(0)),
+            }
+        }
+
+        fn fail(self, channel: &str, times: u32) -> Self {
+            self.failures
+                .lock()
+                .unwrap()
+                .insert(String::from(channel), times);
+            self
+        }
+
+        fn sent_to(&self, channel: &str) -> Vec<String> {
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(c, _)| c == channel)
+                .map(|(_, m)| m.clone())
+                .collect()
+        }
+    }
+
+    impl Sender for StubClient {
+        fn channels(&self) -> Option<Vec<String>> {
+            Some(self.channels.clone())
+        }
+
+        fn privmsg(&self, target: &str, message: &str) -> Result<(), Error> {
+            let mut failures = self.failures.lock().unwrap();
+            match failures.get_mut(target) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    Err(Error::IrcSend(String::from("connection reset")))
+                }
+                _ => {
+                    self.sent
+                        .lock()
+                        .unwrap()
+                        .push((String::from(target), String::from(message)));
+                    Ok(())
+                }
+            }
+        }
+
+        fn reconnect(&self) -> Result<(), Error> {
+            *self.reconnects.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn test_writer(client: &StubClient, cfg: &IrcConfig) -> RealIrcWriter<StubClient> {
+        RealIrcWriter::new(
+            client.clone(),
+            cfg,
+            FormatConfig::default(),
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+    }
+
+    #[test]
+    fn write_prefix_suffix() {
+        let client = StubClient::new(&["#chan"]);
+        let format = FormatConfig {
+            prefix: String::from("[prod] "),
+            suffix: String::from(" //"),
+            ..FormatConfig::default()
+        };
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &test_config(&["#chan"]),
+            format,
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        assert!(writer.write("hello").is_ok());
+        assert_eq!(
+            client.sent_to("#chan"),
+            vec![String::from("[prod] hello //")]
+        );
+    }
+
+    #[test]
+    fn write_splits_lines() {
+        let client = StubClient::new(&["#chan"]);
+        let mut writer = test_writer(&client, &test_config(&["#chan"]));
+
+        assert!(writer.write("first\n  second\n").is_ok());
+        assert_eq!(
+            client.sent_to("#chan"),
+            vec![String::from("first"), String::from("  second")]
+        );
+    }
+
+    #[test]
+    fn multiple_networks() {
+        let mut cfg = config::Config::default();
+        let network = |server: &str, channel: &str| {
+            let mut n = HashMap::new();
+            n.insert(String::from("nickname"), config::Value::from("raccoon"));
+            n.insert(String::from("nick_password"), config::Value::from("secret"));
+            n.insert(String::from("server"), config::Value::from(server));
+            n.insert(String::from("port"), config::Value::from(6697));
+            n.insert(String::from("channels"), config::Value::from(vec![channel]));
+            n
+        };
+        cfg.set(
+            "irc.networks",
+            vec![
+                network("irc.internal.example.com", "#internal"),
+                network("irc.libera.chat", "#public"),
+            ],
+        )
+        .unwrap();
+
+        let networks = networks(&cfg, &slog::Logger::root(slog::Discard, o!())).unwrap();
+        assert_eq!(networks.len(), 2);
+        assert_eq!(networks[0].server, "irc.internal.example.com");
+        assert_eq!(networks[1].channel_specs(), vec![String::from("#public")]);
+
+        // every network gets its own writer
+        let internal = StubClient::new(&["#internal"]);
+        let public = StubClient::new(&["#public"]);
+        let mut writer = Networks {
+            writers: vec![
+                test_writer(&internal, &networks[0]),
+                test_writer(&public, &networks[1]),
+            ],
+        };
+        assert!(writer.write("hello").is_ok());
+        assert_eq!(internal.sent_to("#internal"), vec![String::from("hello")]);
+        assert_eq!(public.sent_to("#public"), vec![String::from("hello")]);
+        assert_eq!(
+            writer.status().channels,
+            vec![String::from("#internal"), String::from("#public")]
+        );
+    }
+
+    #[test]
+    fn single_network() {
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.channels", vec!["#chan"]).unwrap();
+
+        let networks = networks(&cfg, &slog::Logger::root(slog::Discard, o!())).unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].server, "irc.example.com");
+    }
+
+    #[test]
+    fn config_debug_hides_password() {
+        let mut cfg = test_config(&["#chan"]);
+        cfg.nick_password = Secret::new(String::from("hunter2"));
+
+        let formatted = format!("{:?}", cfg);
+        assert!(formatted.contains("nick_password: ***"));
+        assert!(!formatted.contains("hunter2"));
+    }
+
+    #[test]
+    fn nick_password_file() {
+        let path = std::env::temp_dir().join(format!("raccoon-nick-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "inline").unwrap();
+        cfg.set("irc.nick_password_file", path.to_str().unwrap())
+            .unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.channels", vec!["#chan"]).unwrap();
+
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let parsed = networks(&cfg, &logger).unwrap();
+        assert_eq!(parsed[0].nick_password.expose(), "from-file");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(networks(&cfg, &logger).is_err());
+    }
+
+    #[test]
+    fn writer_status() {
+        let client = StubClient::new(&["#one", "#two"]);
+        let writer = test_writer(&client, &test_config(&["#one", "#two", "#three"]));
+
+        assert_eq!(
+            writer.status(),
+            Status {
+                connected: true,
+                channels: vec![String::from("#one"), String::from("#two")],
+            }
+        );
+    }
+
+    #[test]
+    fn deployment_routes() {
+        let client = StubClient::new(&["#ops", "#dev", "#general"]);
+        let mut cfg = test_config(&["#ops", "#dev", "#general"]);
+        cfg.deployment_routes
+            .insert(String::from("production"), vec![String::from("#ops")]);
+        cfg.deployment_routes
+            .insert(String::from("staging"), vec![String::from("#dev")]);
+        let mut writer = test_writer(&client, &cfg);
+
+        let deployment = |env: &str, message: &str| Notification {
+            kind: String::from("deployment"),
+            environment: Some(String::from(env)),
+            message: String::from(message),
+            ..Notification::default()
+        };
+
+        assert!(writer.notify(&deployment("production", "prod")).is_ok());
+        assert!(writer.notify(&deployment("staging", "staging")).is_ok());
+        // environments without a route go everywhere
+        assert!(writer.notify(&deployment("review", "review")).is_ok());
+
+        assert_eq!(
+            client.sent_to("#ops"),
+            vec![String::from("prod"), String::from("review")]
+        );
+        assert_eq!(
+            client.sent_to("#dev"),
+            vec![String::from("staging"), String::from("review")]
+        );
+        assert_eq!(client.sent_to("#general"), vec![String::from("review")]);
+
+        // a deployment hook as Gitlab sends it
+        let data: serde_json::Value =
+            serde_json::from_str(include_str!("../test/deployment.json")).unwrap();
+        let log = slog::Logger::root(slog::Discard, o!());
+        let kind = crate::gitlab::event_kind(None, &data, &log);
+        let mut hook = Notification::new(&kind, &data);
+        hook.message =
+            crate::gitlab::dispatch(&kind, data, &FormatConfig::default(), &log).unwrap();
+        assert!(writer.notify(&hook).is_ok());
+        assert!(client.sent_to("#ops")[2].contains("deployed 279484c0 to production"));
+        assert_eq!(client.sent_to("#dev").len(), 2);
+    }
+
+    #[test]
+    fn routing_table() {
+        let client = StubClient::new(&["#ops", "#app", "#general"]);
+        let mut cfg = test_config(&["#ops", "#app", "#general"]);
+        cfg.deployment_routes
+            .insert(String::from("production"), vec![String::from("#ops")]);
+        let mut channels = HashMap::new();
+        channels.insert(String::from("*:group/app"), vec![String::from("#app")]);
+        channels.insert(String::from("*"), vec![String::from("#general")]);
+        cfg.routing = Some(RouteTable {
+            key: String::from("{object_kind}:{project}"),
+            channels,
+        });
+        let mut writer = test_writer(&client, &cfg);
+
+        let event = |kind: &str, project: &str, message: &str| Notification {
+            kind: String::from(kind),
+            project: Some(String::from(project)),
+            environment: Some(String::from("production")),
+            message: String::from(message),
+            ..Notification::default()
+        };
+
+        assert!(writer.notify(&event("push", "group/app", "app")).is_ok());
+        assert!(writer
+            .notify(&event("push", "group/other", "other"))
+            .is_ok());
+        // deployment routes come first
+        assert!(writer
+            .notify(&event("deployment", "group/app", "deployed"))
+            .is_ok());
+
+        assert_eq!(client.sent_to("#app"), vec![String::from("app")]);
+        assert_eq!(client.sent_to("#general"), vec![String::from("other")]);
+        assert_eq!(client.sent_to("#ops"), vec![String::from("deployed")]);
+    }
+
+    #[test]
+    fn group_webhook() {
+        let client = StubClient::new(&["#api", "#web"]);
+        let mut cfg = test_config(&["#api", "#web"]);
+        let mut channels = HashMap::new();
+        channels.insert(String::from("*:group/api"), vec![String::from("#api")]);
+        channels.insert(String::from("*:group/web"), vec![String::from("#web")]);
+        cfg.routing = Some(RouteTable {
+            key: String::from("{object_kind}:{project}"),
+            channels,
+        });
+        let mut writer = test_writer(&client, &cfg);
+
+        // a group webhook sends the events of all its projects to the same place
+        let event = |kind: &str, file: &str, project: &str| {
+            let mut data: serde_json::Value =
+                serde_json::from_reader(std::fs::File::open(file).unwrap()).unwrap();
+            if data["project"].is_object() {
+                data["project"]["path_with_namespace"] = serde_json::json!(project);
+            } else {
+                data["repository"]["homepage"] =
+                    serde_json::json!(format!("http://gitlab.example.com/{}", project));
+            }
+            Notification {
+                message: format!("{} in {}", kind, project),
+                ..Notification::new(kind, &data)
+            }
+        };
+
+        for (kind, file, project) in &[
+            ("push", "test/push.json", "group/api"),
+            ("issue", "test/issue.json", "group/web"),
+            ("pipeline", "test/pipeline.json", "group/api"),
+            ("build", "test/build.json", "group/web"),
+            ("merge_request", "test/merge_request.json", "group/web"),
+            ("note", "test/comment_issue.json", "group/api"),
+        ] {
+            assert!(writer.notify(&event(kind, file, project)).is_ok());
+        }
+
+        assert_eq!(
+            client.sent_to("#api"),
+            vec![
+                String::from("push in group/api"),
+                String::from("pipeline in group/api"),
+                String::from("note in group/api"),
+            ]
+        );
+        assert_eq!(
+            client.sent_to("#web"),
+            vec![
+                String::from("issue in group/web"),
+                String::from("build in group/web"),
+                String::from("merge_request in group/web"),
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_filters() {
+        let mut cfg = config::Config::default();
+        let channel = |name: &str, events: Vec<&str>, statuses: Vec<&str>| {
+            let mut filters = HashMap::new();
+            filters.insert(String::from("events"), config::Value::from(events));
+            filters.insert(String::from("statuses"), config::Value::from(statuses));
+            let mut c = HashMap::new();
+            c.insert(String::from("name"), config::Value::from(name));
+            c.insert(String::from("filters"), config::Value::from(filters));
+            c
+        };
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set(
+            "irc.channels",
+            vec![
+                channel("#all", vec![], vec![]),
+                channel("#alerts:key", vec!["pipeline"], vec!["failed"]),
+                channel("#Ops", vec!["pipeline"], vec!["failed"]),
+            ],
+        )
+        .unwrap();
+        let networks = networks(&cfg, &slog::Logger::root(slog::Discard, o!())).unwrap();
+        assert_eq!(
+            networks[0].channel_specs(),
+            vec![
+                String::from("#all"),
+                String::from("#alerts:key"),
+                String::from("#Ops")
+            ]
+        );
+
+        // servers may report the channel in a different case than configured
+        let client = StubClient::new(&["#all", "#alerts", "#ops"]);
+        let mut writer = test_writer(&client, &networks[0]);
+        let pipeline = |status: &str| Notification {
+            kind: String::from("pipeline"),
+            status: Some(String::from(status)),
+            message: format!("pipeline {}", status),
+            ..Notification::default()
+        };
+
+        assert!(writer.notify(&pipeline("success")).is_ok());
+        assert!(writer.notify(&pipeline("failed")).is_ok());
+
+        assert_eq!(
+            client.sent_to("#all"),
+            vec![
+                String::from("pipeline success"),
+                String::from("pipeline failed")
+            ]
+        );
+        assert_eq!(
+            client.sent_to("#alerts"),
+            vec![String::from("pipeline failed")]
+        );
+        assert_eq!(
+            client.sent_to("#ops"),
+            vec![String::from("pipeline failed")]
+        );
+    }
+
+    #[test]
+    fn delivery_counters() {
+        let client = StubClient::new(&["#ok", "#broken"]).fail("#broken", 10);
+        let mut writer = test_writer(&client, &test_config(&["#ok", "#broken"]));
+        assert!(writer.write("hello").is_err());
+
+        let deliveries = writer.deliveries();
+        assert_eq!(deliveries.sent.get("#ok"), Some(&1));
+        assert_eq!(deliveries.sent.get("#broken"), None);
+        // the first attempt and both retries
+        assert_eq!(deliveries.failed.get("#broken"), Some(&3));
+        assert_eq!(deliveries.failed.get("#ok"), None);
+    }
+
+    #[test]
+    fn action_framing() {
+        let client = StubClient::new(&["#chan"]);
+        let mut cfg = test_config(&["#chan"]);
+        cfg.use_action = true;
+        let mut writer = test_writer(&client, &cfg);
+
+        let long = "x".repeat(600);
+        assert!(writer.write(&format!("reports a push\n{}", long)).is_ok());
+
+        let sent = client.sent_to("#chan");
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], "\u{1}ACTION reports a push\u{1}");
+        // every part of a split line is a complete action
+        for part in &sent[1..] {
+            assert!(part.starts_with("\u{1}ACTION x"));
+            assert!(part.ends_with("x\u{1}"));
+            assert!(part.len() <= line_budget("#chan"));
+        }
+        assert_eq!(
+            sent[1..]
+                .iter()
+                .map(|p| p
+                    .trim_start_matches("\u{1}ACTION ")
+                    .trim_end_matches('\u{1}'))
+                .collect::<String>(),
+            long
+        );
+    }
+
+    #[test]
+    fn push_modes() {
+        let push = Notification {
+            kind: String::from("push"),
+            message: String::from(
+                "pushed 2 commits to repo\n  b6568db: Update README\n  da15608: Fix typo",
+            ),
+            ..Notification::default()
+        };
+
+        let client = StubClient::new(&["#chan"]);
+        let mut writer = test_writer(&client, &test_config(&["#chan"]));
+        assert!(writer.notify(&push).is_ok());
+        assert_eq!(client.sent_to("#chan").len(), 3);
+
+        let client = StubClient::new(&["#chan"]);
+        let mut cfg = test_config(&["#chan"]);
+        cfg.push_mode = PushMode::Summary;
+        let mut writer = test_writer(&client, &cfg);
+        assert!(writer.notify(&push).is_ok());
+        assert_eq!(
+            client.sent_to("#chan"),
+            vec![String::from("pushed 2 commits to repo")]
+        );
+    }
+
+    #[test]
+    fn rate_limit_with_clock() {
+        let client = StubClient::new(&["#chan"]);
+        let mut cfg = test_config(&["#chan"]);
+        cfg.rate_limit = Some(RateLimit {
+            messages: 1,
+            seconds: 60,
+            queue_size: 5,
+        });
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &cfg,
+            FormatConfig::default(),
+            clock.clone(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        assert!(writer.write("one").is_ok());
+        assert!(writer.write("two").is_ok());
+
+        // no matter how long it really takes, time stands still
+        thread::sleep(FLUSH_INTERVAL * 2);
+        assert_eq!(client.sent_to("#chan"), vec![String::from("one")]);
+
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..20 {
+            if client.sent_to("#chan").len() == 2 {
+                break;
+            }
+            thread::sleep(FLUSH_INTERVAL);
+        }
+        assert_eq!(
+            client.sent_to("#chan"),
+            vec![String::from("one"), String::from("two")]
+        );
+    }
+
+    #[test]
+    fn digest_window() {
+        let client = StubClient::new(&["#chan", "#other"]);
+        let mut cfg = test_config(&["#chan", "#other"]);
+        cfg.digest_window_ms = 5000;
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &cfg,
+            FormatConfig::default(),
+            clock.clone(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        for message in &["one", "two", "three"] {
+            let push = Notification {
+                kind: String::from("push"),
+                message: String::from(*message),
+                ..Notification::default()
+            };
+            assert!(writer.notify(&push).is_ok());
+            clock.advance(Duration::from_secs(1));
+        }
+
+        // the window has not passed yet
+        thread::sleep(FLUSH_INTERVAL * 2);
+        assert!(client.sent_to("#chan").is_empty());
+
+        clock.advance(Duration::from_secs(2));
+        for _ in 0..20 {
+            if client.sent_to("#other").len() == 1 {
+                break;
+            }
+            thread::sleep(FLUSH_INTERVAL);
+        }
+        assert_eq!(
+            client.sent_to("#chan"),
+            vec![String::from("one | two | three")]
+        );
+        assert_eq!(
+            client.sent_to("#other"),
+            vec![String::from("one | two | three")]
+        );
+    }
+
+    /// Keeps the messages of everything logged.
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for Capture {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn overlong_message_warning() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let client = StubClient::new(&["#chan"]);
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &test_config(&["#chan"]),
+            FormatConfig::default(),
+            Arc::new(SystemClock),
+            &slog::Logger::root(Capture(logged.clone()), o!()),
+        );
+
+        let issue = |message: String| Notification {
+            kind: String::from("issue"),
+            message,
+            ..Notification::default()
+        };
+
+        assert!(writer.notify(&issue(String::from("short"))).is_ok());
+        assert!(logged.lock().unwrap().is_empty());
+
+        let long = "🦝".repeat(250);
+        assert!(writer
+            .notify(&issue(format!("first line\n{}", long)))
+            .is_ok());
+        assert_eq!(
+            *logged.lock().unwrap(),
+            vec![format!(
+                "issue message has a line of 1000 bytes, more than the {} that fit on IRC, splitting it",
+                line_budget("#chan")
+            )]
+        );
+
+        // still sent, in parts
+        let sent = client.sent_to("#chan");
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[1], "first line");
+        assert!(sent[2..].iter().all(|p| p.len() <= line_budget("#chan")));
+        assert_eq!(sent[2..].concat(), long);
+
+        // the prefix and suffix count too
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &test_config(&["#chan"]),
+            FormatConfig {
+                prefix: "p".repeat(300),
+                ..FormatConfig::default()
+            },
+            Arc::new(SystemClock),
+            &slog::Logger::root(Capture(logged.clone()), o!()),
+        );
+        assert!(writer.notify(&issue("x".repeat(200))).is_ok());
+        assert_eq!(logged.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decorate_within_budget() {
+        let format = FormatConfig {
+            prefix: String::from("[prod] "),
+            suffix: String::from(" //"),
+            ..FormatConfig::default()
+        };
+
+        assert_eq!(decorate("hello", &format, 100), vec!["[prod] hello //"]);
+
+        // every part gets the prefix and suffix, the message makes room for them
+        let lines = decorate("a very long message", &format, 20);
+        assert_eq!(lines, vec!["[prod] a very lon //", "[prod] g message //"]);
+        assert!(lines.iter().all(|l| l.len() <= 20));
+
+        // never splits a character in half
+        let lines = decorate("🦝🦝🦝🦝🦝", &FormatConfig::default(), 10);
+        assert_eq!(lines, vec!["🦝🦝", "🦝🦝", "🦝"]);
+        // nor drops one that does not fit at all
+        assert_eq!(
+            decorate("🦝🦝", &FormatConfig::default(), 2),
+            vec!["🦝", "🦝"]
+        );
+    }
+
+    #[test]
+    fn write_retries_failed_send() {
+        let client = StubClient::new(&["#chan"]).fail("#chan", 1);
+        let mut writer = test_writer(&client, &test_config(&["#chan"]));
+
+        assert!(writer.write("hello").is_ok());
+        assert_eq!(client.sent_to("#chan"), vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn write_gives_up_after_retries() {
+        let client = StubClient::new(&["#broken", "#chan"]).fail("#broken", 5);
+        let mut writer = test_writer(&client, &test_config(&["#broken", "#chan"]));
+
+        let res = writer.write("hello");
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("#broken"));
+
+        // the next channel still gets the message
+        assert!(client.sent_to("#broken").is_empty());
+        assert_eq!(client.sent_to("#chan"), vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn write_continues_after_failed_channel() {
+        let mut cfg = test_config(&["#moderated", "#good"]);
+        cfg.send_retries = 0;
+        let client = StubClient::new(&["#moderated", "#good"]).fail("#moderated", 1);
+        let mut writer = test_writer(&client, &cfg);
+
+        let err = writer.write("hello").unwrap_err();
+        match err {
+            Error::Delivery { total, ref errors } => {
+                assert_eq!(total, 2);
+                assert_eq!(errors.len(), 1);
+                assert!(errors[0].contains("#moderated"));
+            }
+            ref e => panic!("unexpected error {}", e),
+        }
+        assert!(err.to_string().contains("1 of 2 channels"));
+        assert_eq!(client.sent_to("#good"), vec![String::from("hello")]);
+    }
+
+    #[test]
+    fn writer_reconnect() {
+        let client = StubClient::new(&["#chan"]);
+        let mut writer = test_writer(&client, &test_config(&["#chan"]));
+
+        assert!(writer.reconnect().is_ok());
+        assert_eq!(*client.reconnects.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn reconnect_on_send_failure() {
+        let client = StubClient::new(&["#chan"]).fail("#chan", 100);
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let mut writer = RealIrcWriter::new(
+            client.clone(),
+            &test_config(&["#chan"]),
+            FormatConfig::default(),
+            clock.clone(),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        assert!(writer.write("hello").is_err());
+        assert_eq!(*client.reconnects.lock().unwrap(), 1);
+
+        // not again right away
+        assert!(writer.write("hello").is_err());
+        assert_eq!(*client.reconnects.lock().unwrap(), 1);
+
+        clock.advance(SEND_FAILURE_RECONNECT_INTERVAL);
+        assert!(writer.write("hello").is_err());
+        assert_eq!(*client.reconnects.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn write_not_connected() {
+        let mut writer = RealIrcWriter::new(
+            Connection::default(),
+            &test_config(&["#chan"]),
+            FormatConfig::default(),
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        match writer.write("hello") {
+            Err(Error::NotConnected) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let notification = Notification {
+            kind: String::from("push"),
+            message: String::from("hello"),
+            ..Notification::default()
+        };
+        match writer.notify(&notification) {
+            Err(Error::NotConnected) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn connection_reconnect_request() {
+        let conn = Connection::default();
+        assert!(!conn.take_reconnect_request());
+
+        // not connected, but the IRC thread still gets the message
+        match conn.reconnect() {
+            Err(Error::NotConnected) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(conn.take_reconnect_request());
+        assert!(!conn.take_reconnect_request());
+    }
+
+    #[test]
+    fn init_without_irc_config() {
+        let res = init(
+            &config::Config::default(),
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        );
+
+        match res {
+            Err(Error::Config(_)) => (),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("init succeeded without config"),
+        }
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            assert!(next_delay(0, MAX_RECONNECT_DELAY, &mut rng) <= Duration::from_secs(1));
+            assert!(next_delay(3, MAX_RECONNECT_DELAY, &mut rng) <= Duration::from_secs(8));
+        }
+
+        let cap = Duration::from_secs(10);
+        let delays = (0..1000)
+            .map(|_| next_delay(100, cap, &mut rng))
+            .collect::<Vec<_>>();
+        assert!(delays.iter().all(|d| *d <= cap));
+        // spread over the whole range up to the cap
+        assert!(delays.iter().any(|d| *d > cap / 2));
+        assert!(delays.iter().any(|d| *d < cap / 2));
+    }
+
+    #[test]
+    fn nick_in_use_reclaims_nick() {
+        let msg: Message = ":irc.example.com 433 * raccoon :Nickname is already in use\r\n"
+            .parse()
+            .unwrap();
+
+        let cmds = reclaim_nick(&msg, "raccoon", "secret");
+        assert!(cmds.contains(&Command::PRIVMSG(
+            String::from("NickServ"),
+            String::from("GHOST raccoon secret")
+        )));
+        assert!(cmds.contains(&Command::PRIVMSG(
+            String::from("NickServ"),
+            String::from("REGAIN raccoon secret")
+        )));
+        assert!(cmds.contains(&Command::NICK(String::from("raccoon"))));
+    }
+
+    #[test]
+    fn nick_in_use_ignores_other_messages() {
+        let other_nick: Message = ":irc.example.com 433 * raccoon_ :Nickname is already in use\r\n"
+            .parse()
+            .unwrap();
+        assert!(reclaim_nick(&other_nick, "raccoon", "secret").is_empty());
+
+        let welcome: Message = ":irc.example.com 001 raccoon :Welcome\r\n".parse().unwrap();
+        assert!(reclaim_nick(&welcome, "raccoon", "secret").is_empty());
+    }
+
+    #[test]
+    fn identify_command_template() {
+        assert_eq!(
+            render_identify_command("PRIVMSG NickServ :IDENTIFY {password}", "raccoon", "s3cr3t"),
+            "PRIVMSG NickServ :IDENTIFY s3cr3t"
+        );
+        assert_eq!(
+            render_identify_command(
+                "PRIVMSG AuthServ :AUTH {nick} {password}",
+                "raccoon",
+                "s3cr3t"
+            ),
+            "PRIVMSG AuthServ :AUTH raccoon s3cr3t"
+        );
+
+        let msg: Message = render_identify_command(DEFAULT_IDENTIFY_COMMAND, "raccoon", "s3cr3t")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            msg.command,
+            Command::PRIVMSG(String::from("NickServ"), String::from("IDENTIFY s3cr3t"))
+        );
+    }
+
+    #[test]
+    fn channel_names() {
+        assert!(test_config(&["#chan", "&local", "#with:key"])
+            .validate()
+            .is_ok());
+
+        let err = test_config(&["#ok", "chan", "", "#", "#two words", ":key"])
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "configuration error: invalid channel names on irc.example.com: \
+             \"chan\", \"\", \"#\", \"#two words\", \":key\""
+        );
+    }
+
+    #[test]
+    fn empty_channels() {
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.channels", Vec::<String>::new()).unwrap();
+
+        assert_eq!(
+            validate(&cfg).unwrap_err().to_string(),
+            "configuration error: no channels on irc.example.com"
+        );
+    }
+
+    #[test]
+    fn channel_without_prefix() {
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.channels", vec!["dev", "ops:key", "#chan"])
+            .unwrap();
+
+        let networks = networks(&cfg, &slog::Logger::root(slog::Discard, o!())).unwrap();
+        assert_eq!(
+            networks[0].channel_specs(),
+            vec![
+                String::from("#dev"),
+                String::from("#ops:key"),
+                String::from("#chan")
+            ]
+        );
+    }
+
+    #[test]
+    fn client_config() {
+        let mut cfg = test_config(&["#chan:key"]);
+        cfg.username = Some(String::from("raccoon-bot"));
+        cfg.realname = Some(String::from("Raccoon Gitlab notifier"));
+
+        let client: client::data::config::Config = cfg.into();
+        assert_eq!(client.nickname, Some(String::from("raccoon")));
+        assert_eq!(client.username, Some(String::from("raccoon-bot")));
+        assert_eq!(
+            client.realname,
+            Some(String::from("Raccoon Gitlab notifier"))
+        );
+        assert_eq!(client.channels, Some(vec![String::from("#chan")]));
+
+        let client: client::data::config::Config = test_config(&["#chan"]).into();
+        assert_eq!(client.username, None);
+        assert_eq!(client.realname, None);
+        assert_eq!(client.cert_path, None);
+    }
+
+    #[test]
+    fn tls_cert_path() {
+        let mut cfg = test_config(&["#chan"]);
+        cfg.tls_cert_path = Some(PathBuf::from("/etc/raccoon/internal-ca.der"));
+        let client: client::data::config::Config = cfg.into();
+        assert_eq!(
+            client.cert_path,
+            Some(String::from("/etc/raccoon/internal-ca.der"))
+        );
+        assert_eq!(client.use_ssl, Some(true));
+
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "irc.example.com").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.channels", vec!["#chan"]).unwrap();
+        cfg.set("irc.tls_cert_path", "/nonexistent/ca.der").unwrap();
+        assert!(networks(&cfg, &slog::Logger::root(slog::Discard, o!())).is_err());
+    }
+
+    #[test]
+    fn greet_once() {
+        let message = |line: &str| line.parse::<Message>().unwrap();
+        let names = |channel: &str| {
+            message(&format!(
+                ":irc.example.com 353 raccoon = {} :raccoon alice\r\n",
+                channel
+            ))
+        };
+        let mut greeted = Greeted::default();
+
+        assert!(greeted.greeting(&names("#chan"), false).is_some());
+        // the rest of a long list of names
+        assert_eq!(greeted.greeting(&names("#chan"), false), None);
+        assert_eq!(greeted.greeting(&names("#CHAN"), false), None);
+        assert!(greeted.greeting(&names("&local"), false).is_some());
+        assert_eq!(greeted.greeting(&names("&local"), false), None);
+
+        greeted.joined(&message(":alice!a@example.com JOIN #chan\r\n"), "raccoon");
+        assert_eq!(greeted.greeting(&names("#chan"), false), None);
+        // joining again after a kick
+        greeted.joined(&message(":raccoon!r@example.com JOIN #chan\r\n"), "raccoon");
+        assert!(greeted.greeting(&names("#chan"), false).is_some());
+        assert_eq!(greeted.greeting(&names("#chan"), false), None);
+        assert_eq!(greeted.greeting(&names("#other"), true), None);
+    }
+
+    #[test]
+    fn bouncer_mode() {
+        let names: Message = ":irc.example.com 353 raccoon = #chan :raccoon alice\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            greeting(&names, false),
+            Some(Command::PRIVMSG(
+                String::from("#chan"),
+                String::from("🦝 Hello! I am here to serve your Gitlab notifications!")
+            ))
+        );
+        assert_eq!(greeting(&names, true), None);
+
+        let client: client::data::config::Config = test_config(&["#chan"]).into();
+        assert_eq!(client.channels, Some(vec![String::from("#chan")]));
+
+        // the bouncer is already in the channel
+        let mut cfg = test_config(&["#chan:key"]);
+        cfg.bouncer_mode = true;
+        cfg.join_delay_ms = 500;
+        let client: client::data::config::Config = cfg.into();
+        assert_eq!(client.channels, Some(vec![]));
+        assert_eq!(client.channel_keys, Some(HashMap::new()));
+    }
+
+    #[test]
+    fn paced_joins() {
+        assert_eq!(
+            join_offsets(3, Duration::from_millis(500)),
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(500),
+                Duration::from_millis(1000),
+            ]
+        );
+        assert!(join_offsets(0, Duration::from_millis(500)).is_empty());
+
+        // the client must not join on its own when we pace the joins
+        let mut cfg = test_config(&["#one", "#two:key"]);
+        cfg.join_delay_ms = 500;
+        let client: client::data::config::Config = cfg.into();
+        assert_eq!(client.channels, Some(vec![]));
+        assert_eq!(client.channel_keys, Some(HashMap::new()));
+    }
+
+    #[test]
+    fn test_split_channel_keys() {
+        let chans = vec![
+            String::from("#testchannel:password"),
+            String::from("#nopasschannel"),
+            String::from("#another-channel"),
+        ];
+
+        let (channels, keys) = split_channel_keys(&chans);
+
+        assert_eq!(channels.len(), 3);
+        assert_eq!(keys.len(), 1);
+
+        assert!(keys.contains_key("#testchannel"));
+        assert_eq!(keys["#testchannel"], "password");
+    }
+
+    #[test]
+    fn duplicate_channels() {
+        let mut channels = vec![
+            Channel::Plain(String::from("#dev")),
+            Channel::Plain(String::from("#ops")),
+            Channel::Plain(String::from("#Dev:key")),
+            Channel::Plain(String::from("#dev")),
+        ];
+        assert_eq!(dedup_channels(&mut channels), vec![String::from("#dev")]);
+
+        let (channels, keys) = split_channel_keys(
+            &channels
+                .iter()
+                .map(|c| c.spec().to_owned())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(channels, vec![String::from("#Dev"), String::from("#ops")]);
+        assert_eq!(keys["#Dev"], "key");
+    }
+
+    #[test]
+    fn channel_key_with_colons() {
+        let (channels, keys) = split_channel_keys(&[String::from("#chan:pa:ss")]);
+
+        assert_eq!(channels, vec![String::from("#chan")]);
+        assert_eq!(keys["#chan"], "pa:ss");
+    }
+
+    /// A minimal IRC server for a single client, speaking TLS like real networks do.
+    /// It welcomes the client, answers its joins with the names in the channel
+    /// and hands every message it receives to the test.
+    fn mock_server() -> (u16, mpsc::Receiver<Message>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Arc::new(
+            crate::tls::server_config(
+                Path::new("test/tls/cert.pem"),
+                Path::new("test/tls/key.pem"),
+            )
+            .unwrap(),
+        );
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut session = ServerSession::new(&config);
+            let mut stream = rustls::Stream::new(&mut session, &mut sock);
+            let mut nick = String::from("*");
+            let mut buffer = Vec::new();
+            let mut chunk = [0; 512];
+            loop {
+                let n = match stream.read(&mut chunk) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => n,
+                };
+                buffer.extend_from_slice(&chunk[..n]);
+                while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                    let line = buffer.drain(..=end).collect::<Vec<_>>();
+                    let msg = match String::from_utf8_lossy(&line).parse::<Message>() {
+                        Ok(msg) => msg,
+                        Err(_) => continue,
+                    };
+                    let replies = match msg.command {
+                        Command::NICK(ref n) => {
+                            nick = n.clone();
+                            Vec::new()
+                        }
+                        Command::USER(_, _, _) => vec![
+                            format!(":mock 001 {} :Welcome to the mock network", nick),
+                            format!(":mock 422 {} :MOTD File is missing", nick),
+                        ],
+                        Command::JOIN(ref channels, _, _) => channels
+                            .split(',')
+                            .flat_map(|c| {
+                                vec![
+                                    format!(":{}!raccoon@localhost JOIN {}", nick, c),
+                                    format!(":mock 353 {} = {} :{} alice", nick, c, nick),
+                                    format!(":mock 366 {} {} :End of /NAMES list", nick, c),
+                                ]
+                            })
+                            .collect(),
+                        Command::PING(ref server, _) => {
+                            vec![format!(":mock PONG mock :{}", server)]
+                        }
+                        _ => Vec::new(),
+                    };
+                    for reply in replies {
+                        if write!(stream, "{}\r\n", reply).is_err() {
+                            return;
+                        }
+                    }
+                    if stream.flush().is_err() || tx.send(msg).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        (port, rx)
+    }
+
+    #[test]
+    fn mock_server_delivery() {
+        let (port, received) = mock_server();
+        let mut cfg = config::Config::default();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.server", "localhost").unwrap();
+        cfg.set("irc.port", i64::from(port)).unwrap();
+        cfg.set("irc.tls_cert_path", "test/tls/cert.der").unwrap();
+        cfg.set("irc.channels", vec!["#chan"]).unwrap();
+        let mut writer = init(
+            &cfg,
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+
+        // `init` returns once welcomed, joining comes after that
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while writer.status().channels.is_empty() {
+            assert!(Instant::now() < deadline, "never joined #chan");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(writer.status().connected);
+        writer.write("pipeline failed").unwrap();
+
+        let mut nicks = Vec::new();
+        let mut delivered = false;
+        while let Ok(msg) = received.recv_timeout(Duration::from_secs(5)) {
+            match msg.command {
+                Command::NICK(nick) => nicks.push(nick),
+                Command::PRIVMSG(ref target, ref text)
+                    if target == "#chan" && text.contains("pipeline failed") =>
+                {
+                    delivered = true;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(nicks, vec![String::from("raccoon")]);
+        assert!(delivered);
     }
 }
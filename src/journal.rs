@@ -0,0 +1,50 @@
+use serde_json::json;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::Error;
+
+/// Append-only log of received events, one JSON object per line.
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends an entry for an event of `kind`, either with the
+    /// formatted message or the reason why it could not be formatted.
+    pub fn record(&self, kind: &str, message: &Result<String, Error>) -> Result<(), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let entry = match message {
+            Ok(m) => json!({ "timestamp": timestamp, "kind": kind, "message": m }),
+            Err(e) => json!({ "timestamp": timestamp, "kind": kind, "error": e.to_string() }),
+        };
+
+        let mut file = self.file.lock().map_err(|_| Error::Lock("journal"))?;
+        writeln!(file, "{}", entry)?;
+        Ok(())
+    }
+}
+
+/// Opens the journal at `service.journal_path`, if one is configured.
+pub fn from_config(cfg: &config::Config) -> Result<Option<Journal>, Error> {
+    match cfg.get_str("service.journal_path") {
+        Ok(path) => Journal::open(path).map(Some),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(Error::Config(format!("invalid journal path: {}", e))),
+    }
+}
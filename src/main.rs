@@ -1,4 +1,4 @@
-#![deny(warnings)]
+#![cfg_attr(feature = "strict", deny(warnings))]
 
 #[macro_use]
 extern crate serde_derive;
@@ -15,13 +15,24 @@ use gotham::state::{FromState, State};
 
 use gotham::handler::{HandlerFuture, IntoHandlerError};
 use gotham::helpers::http::response::{create_empty_response, create_response};
-use hyper::{Body, HeaderMap, StatusCode};
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_ENCODING},
+    Body, HeaderMap, Response, StatusCode,
+};
+use tokio::timer::Timeout;
 
 use futures::{future::Future, stream::Stream};
 
+use flate2::read::{GzDecoder, ZlibDecoder};
 use std::{
+    borrow::Cow,
+    io::Read,
+    net::TcpListener,
+    panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
-    sync::{Arc, Mutex, RwLock},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use serde_json::json;
@@ -30,303 +41,3150 @@ use slog::Drain;
 
 use structopt::StructOpt;
 
+mod backend;
+mod clock;
+mod digest;
+mod error;
+mod filter;
+mod format;
 mod gitlab;
+mod glob;
+mod history;
 mod irc;
+mod journal;
+mod notification;
+mod ratelimit;
+mod routing;
+mod secret;
+mod shortener;
+mod stats;
+#[cfg(feature = "systemd")]
+mod systemd;
+mod tls;
+mod webhook;
+
+use crate::error::Error;
+use crate::filter::FilterConfig;
+use crate::format::FormatConfig;
+use crate::irc::IrcWriter;
+use crate::notification::Notification;
 
 #[derive(Clone, StateData)]
 struct AppState {
     logger: Arc<slog::Logger>,
     cfg: Arc<RwLock<config::Config>>,
     irc: Arc<Mutex<Box<irc::IrcWriter + Send>>>,
+    shortener: Arc<shortener::Shortener + Send + Sync>,
+    journal: Option<Arc<journal::Journal>>,
+    history: Arc<Mutex<history::History>>,
+    counters: Arc<Mutex<stats::EventCounters>>,
+    /// Events of kinds we do not format, which are acknowledged but not sent.
+    unknown_events: Arc<Mutex<stats::EventCounters>>,
+    /// How long Gitlab requests took to handle.
+    request_times: Arc<Mutex<stats::RequestTimes>>,
+    started: Instant,
+    started_at: SystemTime,
+    clock: Arc<clock::Clock + Send + Sync>,
+    /// When the last event from Gitlab came in, to tell whether it still sends any.
+    last_event: Arc<Mutex<Option<SystemTime>>>,
+    /// Events held back during quiet hours or while IRC is down.
+    held: Arc<Mutex<Vec<Notification>>>,
+}
+
+impl AppState {
+    fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// When the service started, in seconds since the epoch.
+    fn started_at_secs(&self) -> u64 {
+        self.started_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// When the last event came in, in seconds since the epoch.
+    fn last_event_secs(&self) -> Option<u64> {
+        let last_event = self.last_event.lock().ok().and_then(|l| *l);
+        last_event
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    }
 }
 
+/// Number of formatted events kept in memory by default.
+const DEFAULT_HISTORY_SIZE: usize = 100;
+
+#[cfg(test)]
 fn router(logger: slog::Logger, cfg: config::Config, irc: Box<irc::IrcWriter + Send>) -> Router {
-    let state = AppState {
-        logger: Arc::new(logger),
-        cfg: Arc::new(RwLock::new(cfg)),
-        irc: Arc::new(Mutex::new(irc)),
-    };
+    RouterBuilder::new()
+        .logger(logger)
+        .config(cfg)
+        .notifier(irc)
+        .build()
+}
+
+/// Puts together the router, for tests and for embedding Raccoon in something else.
+/// Unless told otherwise, it logs nothing, uses the default settings, prints
+/// messages to stdout and goes by the clock of the system.
+struct RouterBuilder {
+    logger: slog::Logger,
+    cfg: config::Config,
+    irc: Box<irc::IrcWriter + Send>,
+    clock: Arc<clock::Clock + Send + Sync>,
+}
+
+impl Default for RouterBuilder {
+    fn default() -> Self {
+        RouterBuilder {
+            logger: slog::Logger::root(slog::Discard, o!()),
+            cfg: config::Config::default(),
+            irc: backend::stdout(),
+            clock: Arc::new(clock::SystemClock),
+        }
+    }
+}
+
+impl RouterBuilder {
+    fn new() -> Self {
+        RouterBuilder::default()
+    }
+
+    fn logger(mut self, logger: slog::Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    fn config(mut self, cfg: config::Config) -> Self {
+        self.cfg = cfg;
+        self
+    }
 
-    let middleware = StateMiddleware::new(state);
+    /// Where messages go, like IRC or any of the other backends.
+    fn notifier(mut self, irc: Box<irc::IrcWriter + Send>) -> Self {
+        self.irc = irc;
+        self
+    }
 
-    // create a middleware pipeline from our middleware
-    let pipeline = single_middleware(middleware);
+    /// What tells the time, shared with the backends so they agree on it.
+    fn clock(mut self, clock: Arc<clock::Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-    // construct a basic chain from our pipeline
-    let (chain, pipelines) = single_pipeline(pipeline);
+    fn build(self) -> Router {
+        let RouterBuilder {
+            logger,
+            cfg,
+            irc,
+            clock,
+        } = self;
+        let shortener = shortener::from_config(&cfg).unwrap_or_else(|e| {
+            error!(logger, "not shortening URLs: {}", e);
+            Box::new(shortener::NoopShortener)
+        });
+
+        let journal = journal::from_config(&cfg).unwrap_or_else(|e| {
+            error!(logger, "not writing a journal: {}", e);
+            None
+        });
+
+        let history_size = cfg
+            .get::<usize>("service.history_size")
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+
+        let counters = Arc::new(Mutex::new(stats::EventCounters::default()));
+        if let Ok(stats_cfg) = cfg.get::<stats::StatsConfig>("stats") {
+            stats::log_periodically(Arc::downgrade(&counters), stats_cfg, logger.new(o!()));
+        }
 
-    // build a router with the chain & pipeline
-    build_router(chain, pipelines, |route| {
-        route.post("/gitlab").to(handle_gitlab);
-    })
+        let state = AppState {
+            logger: Arc::new(logger),
+            cfg: Arc::new(RwLock::new(cfg)),
+            irc: Arc::new(Mutex::new(irc)),
+            shortener: Arc::from(shortener),
+            journal: journal.map(Arc::new),
+            history: Arc::new(Mutex::new(history::History::new(history_size))),
+            counters,
+            unknown_events: Arc::new(Mutex::new(stats::EventCounters::default())),
+            request_times: Arc::new(Mutex::new(stats::RequestTimes::default())),
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            clock,
+            last_event: Arc::new(Mutex::new(None)),
+            held: Arc::new(Mutex::new(Vec::new())),
+        };
+        release_held_periodically(&state);
+
+        let middleware = StateMiddleware::new(state);
+
+        // create a middleware pipeline from our middleware
+        let pipeline = single_middleware(middleware);
+
+        // construct a basic chain from our pipeline
+        let (chain, pipelines) = single_pipeline(pipeline);
+
+        // build a router with the chain & pipeline
+        build_router(chain, pipelines, |route| {
+            route.post("/gitlab").to(handle_gitlab);
+            route.get("/health").to(handle_health);
+            route.get("/status").to(handle_status);
+            route.get("/metrics").to(handle_metrics);
+            route
+                .get("/events")
+                .with_query_string_extractor::<EventsQuery>()
+                .to(handle_events);
+            route.scope("/admin", |route| {
+                route.post("/test").to(handle_admin_test);
+                route.post("/reconnect").to(handle_admin_reconnect);
+                route
+                    .post("/replay")
+                    .with_query_string_extractor::<ReplayQuery>()
+                    .to(handle_admin_replay);
+            });
+        })
+    }
 }
 
-fn compare_gitlab_token(headers: &HeaderMap, app_state: &AppState) -> Result<(), String> {
-    match headers.get("X-Gitlab-Token") {
-        Some(gl_token) => {
-            let token: String = app_state
-                .cfg
-                .read()
-                .map_err(|e| format!("failed to lock application config for reading: {}", e))
-                .and_then(|cfg| {
-                    cfg.get("gitlab.token")
-                        .map_err(|e| format!("no gitlab.token in cfg: {}", e))
-                })?;
+/// Header carrying the Gitlab token, unless `gitlab.token_header` says otherwise.
+const DEFAULT_TOKEN_HEADER: &str = "X-Gitlab-Token";
 
-            if &token == gl_token {
+fn compare_gitlab_token(headers: &HeaderMap, app_state: &AppState) -> Result<(), Error> {
+    let cfg = app_state
+        .cfg
+        .read()
+        .map_err(|_| Error::Lock("application config"))?;
+    let header = cfg
+        .get_str("gitlab.token_header")
+        .unwrap_or_else(|_| DEFAULT_TOKEN_HEADER.to_owned());
+
+    match headers.get(header.as_str()) {
+        Some(gl_token) => {
+            let token: String = cfg
+                .get("gitlab.token")
+                .map_err(|e| Error::Config(format!("no gitlab.token in cfg: {}", e)))?;
+
+            let gl_token = gl_token
+                .to_str()
+                .map_err(|_| Error::Token("gitlab token header is not valid UTF-8".to_owned()))?;
+            if token == gl_token {
                 Ok(())
             } else {
-                Err("mismatching gitlab token".to_owned())
+                Err(Error::Token("mismatching gitlab token".to_owned()))
+            }
+        }
+        None => Err(Error::Token("no gitlab token in headers".to_owned())),
+    }
+}
+
+/// Takes `gitlab.token` from the file in `gitlab.token_file` if there is one,
+/// keeping the token out of the configuration and the environment.
+fn read_token_file(cfg: &mut config::Config) -> Result<(), Error> {
+    match cfg.get_str("gitlab.token_file") {
+        Ok(path) => {
+            let token = secret::read(Path::new(&path))?;
+            cfg.set("gitlab.token", token)
+                .map(|_| ())
+                .map_err(|e| Error::Config(format!("failed to set gitlab.token: {}", e)))
+        }
+        Err(config::ConfigError::NotFound(_)) => Ok(()),
+        Err(e) => Err(Error::Config(format!(
+            "failed to parse gitlab.token_file: {}",
+            e
+        ))),
+    }
+}
+
+/// With `gitlab.require_token = false`, any request is trusted.
+fn token_disabled(app_state: &AppState) -> bool {
+    app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|cfg| cfg.get_bool("gitlab.require_token").ok())
+        .map_or(false, |required| !required)
+}
+
+/// The token admin endpoints require, `None` if they are disabled.
+fn admin_token(app_state: &AppState) -> Result<Option<String>, Error> {
+    let cfg = app_state
+        .cfg
+        .read()
+        .map_err(|_| Error::Lock("application config"))?;
+    match cfg.get_str("admin.token") {
+        Ok(token) => Ok(Some(token)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(Error::Config(format!("failed to read admin.token: {}", e))),
+    }
+}
+
+/// Admin tokens are given either in `X-Admin-Token` or as a bearer token.
+fn compare_admin_token(headers: &HeaderMap, token: &str) -> Result<(), Error> {
+    let invalid = |_| Error::Token("admin token header is not valid UTF-8".to_owned());
+    let given = match (headers.get("X-Admin-Token"), headers.get(AUTHORIZATION)) {
+        (Some(admin_token), _) => admin_token.to_str().map_err(invalid)?,
+        (None, Some(auth)) => {
+            let mut parts = auth.to_str().map_err(invalid)?.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("Bearer"), Some(token)) => token,
+                _ => {
+                    return Err(Error::Token(
+                        "authorization is not a bearer token".to_owned(),
+                    ))
+                }
+            }
+        }
+        (None, None) => return Err(Error::Token("no admin token in headers".to_owned())),
+    };
+
+    if given == token {
+        Ok(())
+    } else {
+        Err(Error::Token("mismatching admin token".to_owned()))
+    }
+}
+
+/// Creates a JSON error response, used for all failures.
+fn error_response(state: &State, status: StatusCode, msg: &str) -> Response<Body> {
+    create_response(
+        state,
+        status,
+        mime::APPLICATION_JSON,
+        json!({
+            "code": status.as_u16(),
+            "error": {
+                "message": msg
+            }
+        })
+        .to_string(),
+    )
+}
+
+/// Validates the admin token of the request in `state`,
+/// returning the response to send on failure.
+fn authorize_admin(state: &State) -> Result<(), Response<Body>> {
+    let app_state = AppState::borrow_from(state);
+    match admin_token(app_state) {
+        Ok(Some(token)) => {
+            compare_admin_token(HeaderMap::borrow_from(state), &token).map_err(|e| {
+                error!(app_state.logger, "Failed to validate admin token: {}", e);
+                error_response(state, StatusCode::UNAUTHORIZED, &e.to_string())
+            })
+        }
+        // without a token of their own, admin endpoints do not exist
+        Ok(None) => Err(error_response(
+            state,
+            StatusCode::NOT_FOUND,
+            "admin endpoints are disabled, set admin.token to enable them",
+        )),
+        Err(e) => {
+            error!(app_state.logger, "{}", e);
+            Err(error_response(
+                state,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &e.to_string(),
+            ))
+        }
+    }
+}
+
+const TEST_MESSAGE: &str = "🦝 test message";
+
+fn handle_admin_test(state: State) -> (State, Response<Body>) {
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            match lock_irc(&app_state.irc).write(TEST_MESSAGE) {
+                Ok(()) => create_empty_response(&state, StatusCode::OK),
+                Err(e) => {
+                    error!(
+                        app_state.logger,
+                        "failed to post test message to IRC: {}", e
+                    );
+                    error_response(&state, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                }
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+fn handle_admin_reconnect(state: State) -> (State, Response<Body>) {
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            info!(app_state.logger, "reconnect requested");
+            match lock_irc(&app_state.irc).reconnect() {
+                Ok(()) => create_empty_response(&state, StatusCode::ACCEPTED),
+                Err(e) => {
+                    error!(app_state.logger, "failed to reconnect to IRC: {}", e);
+                    error_response(&state, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                }
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+/// Locks the writer, even if a panic while sending poisoned the lock. The
+/// writer holds no state that a panic could leave half updated, and without
+/// this a single panic would stop every message from then on.
+fn lock_irc(irc: &Mutex<Box<irc::IrcWriter + Send>>) -> MutexGuard<Box<irc::IrcWriter + Send>> {
+    irc.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct ReplayQuery {
+    count: Option<usize>,
+}
+
+const DEFAULT_REPLAY_COUNT: usize = 5;
+
+/// Most messages replayed at once, to not flood the channels.
+const MAX_REPLAY_COUNT: usize = 50;
+
+/// Sends the last few events to IRC again, oldest first, routed like
+/// they were the first time.
+fn replay(app_state: &AppState, count: usize) -> Result<(), Error> {
+    let notifications = app_state
+        .history
+        .lock()
+        .map_err(|_| Error::Lock("history"))?
+        .recent(count.min(MAX_REPLAY_COUNT))
+        .iter()
+        .rev()
+        .map(|e| e.notification.clone())
+        .collect::<Vec<_>>();
+
+    let mut irc = lock_irc(&app_state.irc);
+    for n in notifications {
+        irc.notify(&n)?;
+    }
+
+    Ok(())
+}
+
+fn handle_admin_replay(mut state: State) -> (State, Response<Body>) {
+    let query = ReplayQuery::take_from(&mut state);
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            let count = query.count.unwrap_or(DEFAULT_REPLAY_COUNT);
+            info!(app_state.logger, "replaying {} events", count);
+            match replay(app_state, count) {
+                Ok(()) => create_empty_response(&state, StatusCode::OK),
+                Err(e) => {
+                    error!(app_state.logger, "failed to replay events: {}", e);
+                    error_response(&state, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                }
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+/// Healthy while IRC is connected, 503 while it is down or reconnecting.
+fn handle_health(state: State) -> (State, Response<Body>) {
+    let app_state = AppState::borrow_from(&state);
+    let (status, health) = if is_degraded(&app_state.irc) {
+        (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+    let resp = create_response(
+        &state,
+        status,
+        mime::APPLICATION_JSON,
+        json!({
+            "status": health,
+            "last_event_at": app_state.last_event_secs(),
+        })
+        .to_string(),
+    );
+
+    (state, resp)
+}
+
+fn handle_status(state: State) -> (State, Response<Body>) {
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            let irc = lock_irc(&app_state.irc).status();
+            create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                json!({
+                    "irc": irc,
+                    "started_at": app_state.started_at_secs(),
+                    "last_event_at": app_state.last_event_secs(),
+                    "uptime_seconds": app_state.uptime().as_secs(),
+                })
+                .to_string(),
+            )
+        }
+    };
+
+    (state, resp)
+}
+
+/// Delivery counters per channel, events of unknown kinds and
+/// how long requests took, in the Prometheus text format.
+fn handle_metrics(state: State) -> (State, Response<Body>) {
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            let deliveries = lock_irc(&app_state.irc).deliveries();
+            match (
+                app_state.unknown_events.lock(),
+                app_state.request_times.lock(),
+            ) {
+                (Ok(unknown), Ok(times)) => create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::TEXT_PLAIN,
+                    deliveries.prometheus()
+                        + &unknown.prometheus("unknown_events_total")
+                        + &times.prometheus("gitlab_request_duration_seconds"),
+                ),
+                _ => {
+                    let e = Error::Lock("metrics");
+                    error!(app_state.logger, "failed to get metrics: {}", e);
+                    error_response(&state, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                }
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct EventsQuery {
+    limit: Option<usize>,
+}
+
+const DEFAULT_EVENTS_LIMIT: usize = 20;
+
+fn handle_events(mut state: State) -> (State, Response<Body>) {
+    let query = EventsQuery::take_from(&mut state);
+    let resp = match authorize_admin(&state) {
+        Err(resp) => resp,
+        Ok(()) => {
+            let app_state = AppState::borrow_from(&state);
+            let events = app_state
+                .history
+                .lock()
+                .map_err(|_| Error::Lock("history"))
+                .map(|h| json!(h.recent(query.limit.unwrap_or(DEFAULT_EVENTS_LIMIT))));
+
+            match events {
+                Ok(events) => create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::APPLICATION_JSON,
+                    events.to_string(),
+                ),
+                Err(e) => {
+                    error!(app_state.logger, "failed to list events: {}", e);
+                    error_response(&state, StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                }
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+/// Reads the filter settings, falling back to the defaults.
+fn filter_config(cfg: &RwLock<config::Config>, log: &slog::Logger) -> FilterConfig {
+    cfg.read()
+        .map_err(|_| Error::Lock("config"))
+        .and_then(|cfg| FilterConfig::from_config(&cfg))
+        .unwrap_or_else(|e| {
+            warn!(log, "using default filter settings: {}", e);
+            FilterConfig::default()
+        })
+}
+
+/// Reads the format settings, falling back to the defaults.
+fn format_config(app_state: &AppState, log: &slog::Logger) -> FormatConfig {
+    app_state
+        .cfg
+        .read()
+        .map_err(|_| Error::Lock("config"))
+        .and_then(|cfg| FormatConfig::from_config(&cfg))
+        .unwrap_or_else(|e| {
+            warn!(log, "using default format settings: {}", e);
+            FormatConfig::default()
+        })
+        .at(&*app_state.clock)
+}
+
+/// Largest request body accepted by default, after decompression.
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Decompresses `body` according to its `Content-Encoding`, refusing
+/// to produce more than `max_size` bytes.
+fn decode_body<'a>(
+    headers: &HeaderMap,
+    body: &'a [u8],
+    max_size: usize,
+) -> Result<Cow<'a, [u8]>, Error> {
+    if body.len() > max_size {
+        return Err(Error::BodyTooLarge(max_size));
+    }
+
+    let encoding = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.trim().to_lowercase());
+    let decoder: Box<Read> = match encoding.as_ref().map(String::as_str) {
+        None | Some("identity") => return Ok(Cow::Borrowed(body)),
+        Some("gzip") | Some("x-gzip") => Box::new(GzDecoder::new(body)),
+        Some("deflate") => Box::new(ZlibDecoder::new(body)),
+        Some(e) => return Err(Error::Decompress(format!("unsupported encoding {}", e))),
+    };
+
+    // read one byte too many to know if the limit was exceeded
+    let mut decoded = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| Error::Decompress(e.to_string()))?;
+    if decoded.len() > max_size {
+        return Err(Error::BodyTooLarge(max_size));
+    }
+
+    Ok(Cow::Owned(decoded))
+}
+
+fn process_gitlab(state: &State, body: &[u8]) -> Response<Body> {
+    let headers = HeaderMap::borrow_from(state);
+    let app_state = AppState::borrow_from(state);
+    let log = app_state.logger.new(o!());
+
+    // is this request something we want?
+    if !token_disabled(app_state) {
+        if let Err(e) = compare_gitlab_token(headers, app_state) {
+            error!(log, "Failed to validate Gitlab token: {}", e);
+            return error_response(state, StatusCode::BAD_REQUEST, &e.to_string());
+        }
+    }
+
+    let max_size = app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|c| c.get::<usize>("service.max_body_size").ok())
+        .unwrap_or(DEFAULT_MAX_BODY_SIZE);
+    let body = match decode_body(headers, body, max_size) {
+        Ok(body) => body,
+        Err(e @ Error::BodyTooLarge(_)) => {
+            return error_response(state, StatusCode::PAYLOAD_TOO_LARGE, &e.to_string())
+        }
+        Err(e) => return error_response(state, StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let json: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(json) => json,
+        Err(e) => {
+            return error_response(state, StatusCode::BAD_REQUEST, &Error::from(e).to_string())
+        }
+    };
+    if !json.is_object() {
+        let e = Error::Input(String::from("event is not a JSON object"));
+        return error_response(state, StatusCode::BAD_REQUEST, &e.to_string());
+    }
+
+    // determine kind and format message
+    let event = headers.get("X-Gitlab-Event").and_then(|e| e.to_str().ok());
+    let object_kind = gitlab::event_kind(event, &json, &log);
+    match app_state.counters.lock() {
+        Ok(mut c) => c.increment(&object_kind),
+        Err(_) => error!(log, "{}", Error::Lock("event counters")),
+    }
+    match app_state.last_event.lock() {
+        Ok(mut l) => *l = Some(app_state.clock.system_time()),
+        Err(_) => error!(log, "{}", Error::Lock("last event time")),
+    }
+
+    let format = format_config(app_state, &log);
+    let filters = filter_config(&app_state.cfg, &log);
+    let suppressed =
+        filters.suppress_draft_mr && gitlab::is_draft_merge_request(&object_kind, &json);
+    let mut notification = Notification::new(&object_kind, &json);
+    let unwanted_action = !filters.accepts_action(&notification);
+    let ignored_user = filters.ignores_user(&notification);
+    let unwanted_status = !filters.accepts_build_status(&notification);
+    let unwanted_source = !filters.accepts_pipeline_source(&notification);
+    let skip_ci =
+        gitlab::head_commit_message(&object_kind, &json).map_or(false, |m| filters.skips_ci(m));
+    let msg = gitlab::dispatch(
+        &object_kind,
+        json,
+        &format,
+        &log.new(o!("object_kind" => object_kind.clone())),
+    );
+
+    if let Some(ref journal) = app_state.journal {
+        if let Err(e) = journal.record(&object_kind, &msg) {
+            error!(log, "{}", e);
+        }
+    }
+
+    // send message to irc
+    match msg {
+        Ok(_) if suppressed => debug!(log, "suppressing notification for draft merge request"),
+        Ok(_) if ignored_user => debug!(
+            log,
+            "suppressing notification from {}",
+            notification.user.as_ref().map_or("", String::as_str)
+        ),
+        Ok(_) if unwanted_status => debug!(
+            log,
+            "suppressing notification for {} build",
+            notification.status.as_ref().map_or("", String::as_str)
+        ),
+        Ok(_) if unwanted_source => debug!(
+            log,
+            "suppressing notification for {} pipeline",
+            notification.source.as_ref().map_or("", String::as_str)
+        ),
+        Ok(_) if skip_ci => debug!(log, "suppressing notification for push skipping CI"),
+        Ok(_) if unwanted_action => debug!(
+            log,
+            "suppressing notification for {} action",
+            notification.action.as_ref().map_or("", String::as_str)
+        ),
+        Ok(m) => {
+            let m = if format.shorten_urls {
+                shortener::shorten_urls(&m, &*app_state.shortener, &log)
+            } else {
+                m
+            };
+            debug!(log, "{}", m);
+            notification.message = m;
+            match app_state.history.lock() {
+                Ok(mut h) => h.push(&notification),
+                Err(_) => error!(log, "{}", Error::Lock("history")),
+            }
+
+            if hold_while_degraded(app_state, &notification, &log) {
+                return create_empty_response(state, StatusCode::ACCEPTED);
+            }
+            if !hold_for_quiet_hours(app_state, &filters, &notification, &log) {
+                release_held(&app_state.held, &app_state.irc, &log);
+                if let Err(e) = lock_irc(&app_state.irc).notify(&notification) {
+                    error!(log, "failed to post message to IRC: {}", e);
+                }
+            }
+        }
+        // valid events we have no format for, or without any kind at all,
+        // are acknowledged so that Gitlab does not consider the hook broken
+        Err(Error::UnknownEvent(ref kind)) => {
+            match app_state.unknown_events.lock() {
+                Ok(mut u) => u.increment(kind),
+                Err(_) => error!(log, "{}", Error::Lock("unknown event counters")),
+            }
+            if filters.notify_unknown {
+                notify_unknown(app_state, notification, kind, &log);
+            }
+        }
+        Err(e) => return error_response(state, StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+
+    // return value is only used to signal that we
+    // received the thing, so just send OK in case
+    // we got down here 🦆
+    create_empty_response(state, StatusCode::OK)
+}
+
+/// Kind of the notices about events of unknown kinds, which
+/// can be routed to a channel of their own like any other kind.
+const UNKNOWN_EVENT_KIND: &str = "unknown";
+
+/// Tells IRC that an event of `kind` arrived, which we have no format for.
+fn notify_unknown(
+    app_state: &AppState,
+    notification: Notification,
+    kind: &str,
+    log: &slog::Logger,
+) {
+    let notice = Notification {
+        kind: String::from(UNKNOWN_EVENT_KIND),
+        message: format!("❓ received unknown event: {}", kind),
+        ..notification
+    };
+    if let Err(e) = lock_irc(&app_state.irc).notify(&notice) {
+        error!(log, "failed to send notice about unknown event: {}", e);
+    }
+}
+
+/// Keeps `notification` for later, or drops it, if it arrived
+/// during quiet hours. Returns whether it should not be sent now.
+fn hold_for_quiet_hours(
+    app_state: &AppState,
+    filters: &FilterConfig,
+    notification: &Notification,
+    log: &slog::Logger,
+) -> bool {
+    if !filters.is_quiet(app_state.clock.system_time()) {
+        return false;
+    }
+
+    if filters.quiet_hours.as_ref().map_or(false, |q| q.drop) {
+        debug!(
+            log,
+            "dropping {} event during quiet hours", notification.kind
+        );
+        return true;
+    }
+
+    debug!(
+        log,
+        "holding {} event until quiet hours end", notification.kind
+    );
+    hold(app_state, notification, log)
+}
+
+/// Most events held back at once by default.
+const DEFAULT_MAX_HELD_EVENTS: usize = 1000;
+
+/// Keeps `notification` to send it later. Like the rate limit queues, the held
+/// events are capped, at `service.max_held_events`, but the oldest are dropped
+/// to make room. Returns whether it was kept.
+fn hold(app_state: &AppState, notification: &Notification, log: &slog::Logger) -> bool {
+    let max = app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|c| c.get::<usize>("service.max_held_events").ok())
+        .unwrap_or(DEFAULT_MAX_HELD_EVENTS)
+        .max(1);
+
+    match app_state.held.lock() {
+        Ok(mut held) => {
+            let excess = (held.len() + 1).saturating_sub(max);
+            for dropped in held.drain(..excess) {
+                warn!(
+                    log,
+                    "more than {} events held back, dropping a {} event", max, dropped.kind
+                );
             }
+            held.push(notification.clone());
+            true
+        }
+        Err(_) => {
+            error!(log, "{}", Error::Lock("held events"));
+            false
+        }
+    }
+}
+
+/// Whether IRC is down or reconnecting, going by the status of the writer.
+fn is_degraded(irc: &Mutex<Box<irc::IrcWriter + Send>>) -> bool {
+    !lock_irc(irc).status().connected
+}
+
+/// Keeps `notification` until IRC is back if it is down and
+/// `service.buffer_when_degraded` is set. Returns whether it was kept.
+fn hold_while_degraded(
+    app_state: &AppState,
+    notification: &Notification,
+    log: &slog::Logger,
+) -> bool {
+    let buffer = app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|c| c.get_bool("service.buffer_when_degraded").ok())
+        .unwrap_or(false);
+    if !buffer || !is_degraded(&app_state.irc) {
+        return false;
+    }
+
+    debug!(log, "holding {} event until IRC is back", notification.kind);
+    hold(app_state, notification, log)
+}
+
+/// Sends the events held back during quiet hours or while IRC was
+/// down, oldest first, unless it still is.
+fn release_held(
+    held: &Mutex<Vec<Notification>>,
+    irc: &Mutex<Box<irc::IrcWriter + Send>>,
+    log: &slog::Logger,
+) {
+    if is_degraded(irc) {
+        return;
+    }
+
+    let notifications = match held.lock() {
+        Ok(mut held) => held.drain(..).collect::<Vec<_>>(),
+        Err(_) => {
+            error!(log, "{}", Error::Lock("held events"));
+            return;
+        }
+    };
+    if notifications.is_empty() {
+        return;
+    }
+
+    info!(log, "sending {} held events", notifications.len());
+    for notification in notifications {
+        if let Err(e) = lock_irc(irc).notify(&notification) {
+            error!(log, "failed to post message to IRC: {}", e);
+        }
+    }
+}
+
+/// How often to check whether held events can be sent.
+const HELD_EVENTS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sends held events once quiet hours end and IRC is back,
+/// even if no new event comes in.
+fn release_held_periodically(app_state: &AppState) {
+    let held = Arc::downgrade(&app_state.held);
+    let irc = Arc::downgrade(&app_state.irc);
+    let cfg = Arc::downgrade(&app_state.cfg);
+    let clock = app_state.clock.clone();
+    let log = app_state.logger.new(o!());
+    thread::spawn(move || loop {
+        thread::sleep(HELD_EVENTS_CHECK_INTERVAL);
+        let (held, irc, cfg) = match (held.upgrade(), irc.upgrade(), cfg.upgrade()) {
+            (Some(held), Some(irc), Some(cfg)) => (held, irc, cfg),
+            _ => return,
+        };
+
+        if !filter_config(&cfg, &log).is_quiet(clock.system_time()) {
+            release_held(&held, &irc, &log);
         }
-        None => Err("no gitlab token in headers".to_owned()),
+    });
+}
+
+/// How long handling a Gitlab request may take before we warn about it.
+const DEFAULT_SLOW_REQUEST_MS: u64 = 1000;
+
+/// Records how long handling a request took, warning if it
+/// took longer than `service.slow_request_ms`.
+fn record_request_time(app_state: &AppState, took: Duration) {
+    let log = &app_state.logger;
+    match app_state.request_times.lock() {
+        Ok(mut times) => times.observe(took),
+        Err(_) => error!(log, "{}", Error::Lock("request times")),
+    }
+
+    let slow_ms = app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|c| c.get::<u64>("service.slow_request_ms").ok())
+        .unwrap_or(DEFAULT_SLOW_REQUEST_MS);
+    if took > Duration::from_millis(slow_ms) {
+        warn!(
+            log,
+            "handling a Gitlab request took {:?}, more than the {} ms of service.slow_request_ms",
+            took,
+            slow_ms
+        );
     }
 }
 
+/// How long to wait for the body of a Gitlab request by default,
+/// about when Gitlab gives up on the webhook anyway.
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 10_000;
+
+fn request_timeout(app_state: &AppState) -> Duration {
+    let timeout_ms = app_state
+        .cfg
+        .read()
+        .ok()
+        .and_then(|c| c.get::<u64>("service.request_timeout_ms").ok())
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS);
+    Duration::from_millis(timeout_ms)
+}
+
 fn handle_gitlab(mut state: State) -> Box<HandlerFuture> {
-    let f = Body::take_from(&mut state).concat2().then(|b| match b {
+    let timeout = request_timeout(AppState::borrow_from(&state));
+    // slow or stalled clients would otherwise hold on to a worker for good
+    let body = Timeout::new(Body::take_from(&mut state).concat2(), timeout);
+    let f = body.then(move |b| match b {
         Ok(vb) => {
-            let headers = HeaderMap::borrow_from(&state);
-            match serde_json::from_slice(&vb) {
-                Ok(json) => {
-                    let app_state = AppState::borrow_from(&state);
-                    let log = app_state.logger.new(o!());
-
-                    // is this request something we want?
-                    if let Err(e) = compare_gitlab_token(headers, app_state) {
-                        error!(log, "Failed to validate Gitlab token: {}", e);
-                        let resp = create_empty_response(&state, StatusCode::BAD_REQUEST);
-                        return Ok((state, resp));
-                    }
+            let started = AppState::borrow_from(&state).clock.now();
+            // a panic while formatting or sending should not take down
+            // the connection without telling Gitlab what happened
+            let resp = panic::catch_unwind(AssertUnwindSafe(|| process_gitlab(&state, &vb)));
+            let app_state = AppState::borrow_from(&state);
+            record_request_time(app_state, app_state.clock.now() - started);
+            match resp {
+                Ok(resp) => Ok((state, resp)),
+                Err(_) => {
+                    error!(
+                        AppState::borrow_from(&state).logger,
+                        "panic while handling Gitlab request"
+                    );
+                    let resp = error_response(
+                        &state,
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal server error",
+                    );
+                    Ok((state, resp))
+                }
+            }
+        }
+        Err(ref e) if e.is_elapsed() => {
+            warn!(
+                AppState::borrow_from(&state).logger,
+                "request body did not arrive within {:?}", timeout
+            );
+            let mut resp = error_response(
+                &state,
+                StatusCode::REQUEST_TIMEOUT,
+                "request body did not arrive in time",
+            );
+            // the rest of the body may still come, so do not keep the connection
+            resp.headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+            Ok((state, resp))
+        }
+        Err(e) => Err((state, e.into_handler_error())),
+    });
+
+    Box::new(f)
+}
+
+#[derive(StructOpt, Debug)]
+/// Raccoon is a service that accepts Gitlab HTTP hooks as described at
+/// https://docs.gitlab.com/ee/user/project/integrations/webhooks.html
+/// and sends the resulting formatted text to IRC.
+struct Opt {
+    #[structopt(
+        parse(from_os_str),
+        short = "c",
+        long = "config",
+        raw(global = "true", number_of_values = "1")
+    )]
+    /// Config file to use. This overrides the standard config
+    /// file resolution. See man page for config file format and
+    /// resolution order if this parameter is not specified.
+    /// Can be given more than once, later files override the
+    /// settings of earlier ones.
+    config: Vec<PathBuf>,
+
+    #[structopt(short = "p", long = "port")]
+    /// Port to bind the service to, default is 7878.
+    /// Can also be set in the settings file with the setting `service.port`.
+    port: Option<u16>,
+
+    #[structopt(short = "b", long = "bind")]
+    /// Address to bind the service to, default is 127.0.0.1.
+    /// Can also be set in the settings file with the setting `service.bind`.
+    bind: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "format")]
+    /// Prints the message an event would be sent as, without
+    /// sending it anywhere.
+    Format {
+        #[structopt(short = "k", long = "kind")]
+        /// Kind of the event, like push or merge_request.
+        /// Detected from the event if not given.
+        kind: Option<String>,
+
+        #[structopt(parse(from_os_str), short = "f", long = "file")]
+        /// File with the JSON body of the event, as Gitlab sends it.
+        file: PathBuf,
+    },
+
+    #[structopt(name = "check")]
+    /// Checks the configuration for problems and exits,
+    /// without connecting anywhere.
+    Check,
+}
+
+/// Problems with `cfg` that would keep Raccoon from starting or working.
+fn check_config(cfg: &config::Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let require_token = cfg.get_bool("gitlab.require_token").unwrap_or(true);
+    if require_token && cfg.get_str("gitlab.token").is_err() {
+        problems.push(String::from(
+            "gitlab.token or gitlab.token_file is required unless gitlab.require_token is false",
+        ));
+    }
+
+    match backend::backends(cfg) {
+        Ok(backends) => {
+            for b in &backends {
+                match b.as_str() {
+                    "irc" => {
+                        if let Err(e) = irc::validate(cfg) {
+                            problems.push(e.to_string());
+                        }
+                    }
+                    "stdout" => (),
+                    "webhook" => {
+                        let log = slog::Logger::root(slog::Discard, o!());
+                        let clock = Arc::new(clock::SystemClock);
+                        if let Err(e) = webhook::WebhookWriter::from_config(cfg, clock, &log) {
+                            problems.push(e.to_string());
+                        }
+                    }
+                    other => problems.push(format!("unknown backend {}", other)),
+                }
+            }
+        }
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    if let Err(e) = FilterConfig::from_config(cfg) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = backend::filters(cfg) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = backend::project_prefixes(cfg) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = FormatConfig::from_config(cfg) {
+        problems.push(e.to_string());
+    }
+    let mut service = cfg.clone();
+    match set_service_defaults(&mut service).and_then(|_| service.get::<ServiceConfig>("service")) {
+        Ok(service) => {
+            if let Err(e) = service.tls() {
+                problems.push(e);
+            }
+        }
+        Err(e) => problems.push(format!("failed to parse service settings: {}", e)),
+    }
+
+    problems
+}
+
+/// Formats the event in `file` like it would be sent.
+fn format_file(
+    kind: Option<&str>,
+    file: &Path,
+    format: &FormatConfig,
+    log: &slog::Logger,
+) -> Result<String, Error> {
+    let contents = std::fs::read(file)
+        .map_err(|e| Error::Input(format!("failed to read {}: {}", file.display(), e)))?;
+    let json: serde_json::Value = serde_json::from_slice(&contents)?;
+    let kind = kind
+        .map(String::from)
+        .unwrap_or_else(|| gitlab::event_kind(None, &json, log));
+
+    gitlab::dispatch(&kind, json, format, log)
+}
+
+/// Listens on localhost unless `service.bind` and `service.port` say otherwise.
+fn set_service_defaults(cfg: &mut config::Config) -> Result<(), config::ConfigError> {
+    cfg.set_default("service.bind", "127.0.0.1".to_owned())?;
+    cfg.set_default("service.port", 7878)?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct ServiceConfig {
+    bind: String,
+    port: u16,
+    #[serde(default = "default_bind_retries")]
+    bind_retries: u32,
+    #[serde(default = "default_bind_retry_delay_ms")]
+    bind_retry_delay_ms: u64,
+    /// Number of threads serving requests.
+    #[serde(default = "num_cpus::get")]
+    threads: usize,
+    /// PEM files to serve HTTPS with, instead of plain HTTP.
+    #[serde(default)]
+    tls_cert: Option<PathBuf>,
+    #[serde(default)]
+    tls_key: Option<PathBuf>,
+}
+
+/// How requests are served.
+#[derive(Debug, PartialEq)]
+enum Scheme {
+    Http,
+    Https { cert: PathBuf, key: PathBuf },
+}
+
+/// Most threads to serve requests with, anything more is surely a typo.
+const MAX_THREADS: usize = 256;
+
+impl ServiceConfig {
+    fn threads(&self) -> usize {
+        self.threads.max(1).min(MAX_THREADS)
+    }
+
+    /// HTTPS if there is both a certificate and a key, plain HTTP if there is neither.
+    fn scheme(&self) -> Result<Scheme, String> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Scheme::Https {
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            (None, None) => Ok(Scheme::Http),
+            _ => Err(String::from(
+                "service.tls_cert and service.tls_key need to be set together",
+            )),
+        }
+    }
+
+    /// The settings to serve HTTPS with, if requests are to be served that way.
+    fn tls(&self) -> Result<Option<gotham::rustls::ServerConfig>, String> {
+        match self.scheme()? {
+            Scheme::Http => Ok(None),
+            Scheme::Https { cert, key } => tls::server_config(&cert, &key)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn default_bind_retries() -> u32 {
+    5
+}
+
+fn default_bind_retry_delay_ms() -> u64 {
+    500
+}
+
+/// Longest time to wait between two attempts to bind.
+const MAX_BIND_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff between attempts to bind, starting at `base`.
+fn bind_retry_delay(base: Duration, attempt: u32) -> Duration {
+    MAX_BIND_RETRY_DELAY.min(base * (1 << attempt.min(16)))
+}
+
+/// Waits for `addr` to become available, e.g. while a previous
+/// instance is still shutting down during a rolling restart.
+fn wait_for_bind(addr: &str, cfg: &ServiceConfig, log: &slog::Logger) -> Result<(), String> {
+    let base = Duration::from_millis(cfg.bind_retry_delay_ms);
+    let mut attempt = 0;
+    loop {
+        match TcpListener::bind(addr) {
+            // gotham binds on its own, this was only to check
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < cfg.bind_retries => {
+                let delay = bind_retry_delay(base, attempt);
+                warn!(
+                    log,
+                    "failed to bind to {}: {}, retrying in {:?}", addr, e, delay
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "failed to bind to {} after {} attempts: {}",
+                    addr,
+                    attempt + 1,
+                    e
+                ))
+            }
+        }
+    }
+}
+
+/// Describes what this instance of raccoon is set up to do.
+fn startup_summary(cfg: &config::Config, service: &ServiceConfig, addr: &str) -> Vec<String> {
+    let filters = FilterConfig::from_config(cfg)
+        .map(|f| f.enabled().join(", "))
+        .unwrap_or_default();
+
+    let backends = backend::backends(cfg).unwrap_or_default();
+
+    let scheme = match service.scheme() {
+        Ok(Scheme::Https { .. }) => "https",
+        _ => "http",
+    };
+    let mut summary = vec![format!("listening for requests at {}://{}", scheme, addr)];
+    if backends.iter().any(|b| b == "irc") {
+        summary.extend(irc::summary(cfg));
+    }
+    summary.extend(vec![
+        format!(
+            "event filters: {}",
+            if filters.is_empty() {
+                "none"
+            } else {
+                filters.as_str()
+            }
+        ),
+        format!("backends: {}", backends.join(", ")),
+    ]);
+
+    summary
+}
+
+/// How log messages get to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Logging {
+    /// Written by a background thread, the default.
+    Async,
+    /// Written right away, so that nothing is lost or out of order
+    /// should Raccoon crash, at the cost of slower logging.
+    Sync,
+}
+
+/// Reads `service.sync_logging`.
+fn logging(cfg: &config::Config) -> Logging {
+    if cfg.get_bool("service.sync_logging").unwrap_or(false) {
+        Logging::Sync
+    } else {
+        Logging::Async
+    }
+}
+
+fn logger(logging: Logging) -> slog::Logger {
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = slog_term::FullFormat::new(decorator).build().fuse();
+    match logging {
+        Logging::Async => slog::Logger::root(slog_async::Async::new(drain).build().fuse(), o!()),
+        Logging::Sync => slog::Logger::root(Mutex::new(drain).fuse(), o!()),
+    }
+}
+
+/// Reads the configuration from `paths`, in order so that later files override
+/// earlier ones, or from the standard locations if there are none.
+fn read_config(paths: &[PathBuf], log: &slog::Logger) -> Result<config::Config, String> {
+    let mut cfg = config::Config::default();
+    for c in paths {
+        info!(
+            log,
+            "reading raccoon config file {} as specified on the command line",
+            c.display()
+        );
+
+        cfg.merge(config::File::with_name(
+            c.to_str().unwrap_or("<invalid-string>"),
+        ))
+        .map_err(|e| {
+            error!(log, "failed to read config: {}", e);
+            e.to_string()
+        })?;
+    }
+
+    if paths.is_empty() {
+        info!(log, "reading raccoon config file");
+        match xdg::BaseDirectories::with_prefix("raccoon") {
+            Ok(xdg_dirs) => {
+                if let Some(f) = xdg_dirs.find_config_file("raccoon.toml") {
+                    info!(log, "using config file from {}", f.display());
+                    cfg.merge(config::File::with_name(
+                        f.to_str().unwrap_or("invalid-string"),
+                    ))
+                    .map_err(|e| format!("failed to parse cfg at {}: {}", f.display(), e))?;
+                }
+            }
+            Err(e) => warn!(log, "failed to get XDG directories: {}", e),
+        };
+
+        if Path::new("./raccoon").exists() {
+            info!(log, "using config file in current directory");
+            cfg.merge(config::File::with_name("./raccoon"))
+                .map_err(|e| {
+                    error!(log, "failed to read config: {}", e);
+                    e.to_string()
+                })?;
+        }
+    }
+
+    cfg.merge(config::Environment::with_prefix("RACCOON"))
+        .map_err(|e| {
+            error!(log, "failed to read environment settings: {}", e);
+            e.to_string()
+        })?;
+
+    read_token_file(&mut cfg).map_err(|e| {
+        error!(log, "{}", e);
+        e.to_string()
+    })?;
+
+    Ok(cfg)
+}
+
+pub fn main() -> Result<(), String> {
+    // the configuration decides how to log, so start out with the default
+    let mut log = logger(Logging::Async);
+
+    let opt = Opt::from_args();
+
+    let mut cfg = read_config(&opt.config, &log)?;
+
+    if logging(&cfg) == Logging::Sync {
+        log = logger(Logging::Sync);
+        info!(log, "logging synchronously");
+    }
+
+    match opt.command {
+        Some(Command::Format { kind, file }) => {
+            let format = FormatConfig::from_config(&cfg).map_err(|e| e.to_string())?;
+            let message = format_file(kind.as_ref().map(String::as_str), &file, &format, &log)
+                .map_err(|e| e.to_string())?;
+            println!("{}", message);
+            return Ok(());
+        }
+        Some(Command::Check) => {
+            let problems = check_config(&cfg);
+            if problems.is_empty() {
+                println!("configuration is valid");
+                return Ok(());
+            }
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            return Err(format!("found {} configuration problems", problems.len()));
+        }
+        None => (),
+    }
+
+    if !cfg.get_bool("gitlab.require_token").unwrap_or(true) {
+        warn!(
+            log,
+            "gitlab.require_token is false, requests are accepted without a token"
+        );
+    }
+
+    let clock: Arc<clock::Clock + Send + Sync> = Arc::new(clock::SystemClock);
+    let mut writer = backend::init(&cfg, clock.clone(), &log).map_err(|e| {
+        error!(log, "failed to initialize backends: {}", e);
+        e.to_string()
+    })?;
+
+    set_service_defaults(&mut cfg).map_err(|e| {
+        error!(log, "failed to set default service settings: {}", e);
+        e.to_string()
+    })?;
+
+    let service_config: ServiceConfig = cfg.get("service").map_err(|e| {
+        error!(log, "failed to parse service settings: {}", e);
+        e.to_string()
+    })?;
+
+    let tls_config = service_config.tls().map_err(|e| {
+        error!(log, "failed to set up TLS: {}", e);
+        e
+    })?;
+
+    let addr = format!(
+        "{}:{}",
+        opt.bind.unwrap_or_else(|| service_config.bind.clone()),
+        opt.port.unwrap_or(service_config.port)
+    );
+
+    for line in startup_summary(&cfg, &service_config, &addr) {
+        info!(log, "{}", line);
+    }
+
+    if cfg.get_bool("irc.announce_startup").unwrap_or(false) {
+        let announcement = format!("🦝 Raccoon v{} online", env!("CARGO_PKG_VERSION"));
+        if let Err(e) = writer.write(&announcement) {
+            warn!(log, "failed to announce startup: {}", e);
+        }
+    }
+
+    wait_for_bind(&addr, &service_config, &log).map_err(|e| {
+        error!(log, "{}", e);
+        e
+    })?;
+
+    #[cfg(feature = "systemd")]
+    systemd::ready(&log);
+
+    let threads = service_config.threads();
+    info!(log, "serving requests with {} threads", threads);
+    let handler = RouterBuilder::new()
+        .logger(log)
+        .config(cfg)
+        .notifier(writer)
+        .clock(clock)
+        .build();
+    match tls_config {
+        Some(tls_config) => gotham::tls::start_with_num_threads(addr, handler, tls_config, threads),
+        None => gotham::start_with_num_threads(addr, handler, threads),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gotham::test::TestServer;
+    use hyper::{header::HeaderValue, StatusCode};
+    use mime;
+
+    macro_rules! test_settings {
+        () => {{
+            let mut cfg = config::Config::default();
+            cfg.set("gitlab.token", "TEST_TOKEN").unwrap();
+            cfg.set("admin.token", "TEST_TOKEN").unwrap();
+            cfg
+        }};
+    }
+
+    #[derive(Clone)]
+    pub struct FakeIrcWriter {
+        pub buffer: Arc<RwLock<String>>,
+        pub reconnects: Arc<RwLock<u32>>,
+        pub channels: Vec<String>,
+    }
+
+    impl FakeIrcWriter {
+        pub fn new() -> Self {
+            FakeIrcWriter {
+                buffer: Arc::new(RwLock::new(String::new())),
+                reconnects: Arc::new(RwLock::new(0)),
+                channels: Vec::new(),
+            }
+        }
+
+        pub fn contains(&self, sub: &str) -> bool {
+            let s = self.buffer.read().unwrap();
+            s.contains(sub)
+        }
+    }
+
+    impl irc::IrcWriter for FakeIrcWriter {
+        fn write(&mut self, message: &str) -> Result<(), Error> {
+            let mut b = self.buffer.write().unwrap();
+            b.push_str(message);
+            Ok(())
+        }
+
+        fn reconnect(&mut self) -> Result<(), Error> {
+            *self.reconnects.write().unwrap() += 1;
+            Ok(())
+        }
+
+        fn status(&self) -> irc::Status {
+            irc::Status {
+                connected: true,
+                channels: self.channels.clone(),
+            }
+        }
+    }
+
+    struct FailingIrcWriter;
+
+    impl irc::IrcWriter for FailingIrcWriter {
+        fn write(&mut self, _message: &str) -> Result<(), Error> {
+            Err(Error::NotConnected)
+        }
+    }
+
+    /// Records the kind of every notification before delivering it to `irc`.
+    struct NotifyingIrcWriter {
+        kinds: Arc<RwLock<Vec<String>>>,
+        irc: FakeIrcWriter,
+    }
+
+    impl irc::IrcWriter for NotifyingIrcWriter {
+        fn write(&mut self, message: &str) -> Result<(), Error> {
+            self.irc.write(message)
+        }
+
+        fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+            self.kinds.write().unwrap().push(notification.kind.clone());
+            self.irc.write(&notification.message)
+        }
+    }
+
+    /// Panics on the first message, delivering the ones after it to `irc`.
+    struct PanickingIrcWriter {
+        panicked: bool,
+        irc: FakeIrcWriter,
+    }
+
+    impl irc::IrcWriter for PanickingIrcWriter {
+        fn write(&mut self, message: &str) -> Result<(), Error> {
+            if !self.panicked {
+                self.panicked = true;
+                panic!("formatting went horribly wrong");
+            }
+            self.irc.write(message)
+        }
+
+        fn status(&self) -> irc::Status {
+            self.irc.status()
+        }
+    }
+
+    #[test]
+    fn gitlab_panic() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(PanickingIrcWriter {
+                panicked: false,
+                irc: irc.clone(),
+            }),
+        ))
+        .unwrap();
+        let post_push = || {
+            test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    include_str!("../test/push.json"),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap()
+        };
+
+        let response = post_push();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.read_utf8_body().unwrap();
+        assert!(body.contains("Internal server error"));
+
+        // the panic must not lock everyone else out of the writer
+        assert_eq!(post_push().status(), StatusCode::OK);
+        assert!(irc.contains("pushed"));
+        let response = test_server
+            .client()
+            .get("http://localhost/health")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn admin_test_message() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert!(irc.contains("🦝 test message"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn admin_test_message_separate_token() {
+        let mut cfg = test_settings!();
+        cfg.set("admin.token", "ADMIN_TOKEN").unwrap();
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert!(!irc.contains("test message"));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("ADMIN_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert!(irc.contains("test message"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn admin_bearer_token() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header(
+                "Authorization",
+                HeaderValue::from_static("Bearer TEST_TOKEN"),
+            )
+            .perform()
+            .unwrap();
+
+        assert!(irc.contains("test message"));
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header(
+                "Authorization",
+                HeaderValue::from_static("Basic TEST_TOKEN"),
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn admin_disabled() {
+        // the Gitlab token is not enough
+        let mut cfg = config::Config::default();
+        cfg.set("gitlab.token", "TEST_TOKEN").unwrap();
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert!(!irc.contains("test message"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn admin_test_message_failure() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FailingIrcWriter),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/test", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn admin_reconnect() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/reconnect", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(*irc.reconnects.read().unwrap(), 1);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn admin_reconnect_invalid_token() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/reconnect", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("WRONG_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(*irc.reconnects.read().unwrap(), 0);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn test_state(cfg: config::Config) -> AppState {
+        AppState {
+            logger: Arc::new(slog::Logger::root(slog::Discard, o!())),
+            cfg: Arc::new(RwLock::new(cfg)),
+            irc: Arc::new(Mutex::new(Box::new(FakeIrcWriter::new()))),
+            shortener: Arc::new(shortener::NoopShortener),
+            journal: None,
+            history: Arc::new(Mutex::new(history::History::new(DEFAULT_HISTORY_SIZE))),
+            counters: Arc::new(Mutex::new(stats::EventCounters::default())),
+            unknown_events: Arc::new(Mutex::new(stats::EventCounters::default())),
+            request_times: Arc::new(Mutex::new(stats::RequestTimes::default())),
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            clock: Arc::new(clock::SystemClock),
+            last_event: Arc::new(Mutex::new(None)),
+            held: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    #[test]
+    fn quiet_hours() {
+        let mut cfg = test_settings!();
+        cfg.set("filters.quiet_hours.start", "22:00").unwrap();
+        cfg.set("filters.quiet_hours.end", "07:00").unwrap();
+        let irc = FakeIrcWriter::new();
+        // 1970-01-01 23:00 UTC
+        let clock = Arc::new(clock::MockClock::new(
+            UNIX_EPOCH + Duration::from_secs(23 * 60 * 60),
+        ));
+        let app_state = AppState {
+            irc: Arc::new(Mutex::new(Box::new(irc.clone()))),
+            clock: clock.clone(),
+            ..test_state(cfg)
+        };
+        let log = slog::Logger::root(slog::Discard, o!());
+        let filters = filter_config(&app_state.cfg, &log);
+        let notification = |message: &str| Notification {
+            kind: String::from("push"),
+            message: String::from(message),
+            ..Notification::default()
+        };
+
+        assert!(hold_for_quiet_hours(
+            &app_state,
+            &filters,
+            &notification("overnight push"),
+            &log
+        ));
+        assert_eq!(app_state.held.lock().unwrap().len(), 1);
+        assert!(!irc.contains("overnight push"));
+
+        // 08:00, the window is over
+        clock.advance(Duration::from_secs(9 * 60 * 60));
+        assert!(!hold_for_quiet_hours(
+            &app_state,
+            &filters,
+            &notification("morning push"),
+            &log
+        ));
+        release_held(&app_state.held, &app_state.irc, &log);
+        assert!(irc.contains("overnight push"));
+        assert!(app_state.held.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn quiet_hours_drop() {
+        let mut cfg = test_settings!();
+        cfg.set("filters.quiet_hours.start", "22:00").unwrap();
+        cfg.set("filters.quiet_hours.end", "07:00").unwrap();
+        cfg.set("filters.quiet_hours.drop", true).unwrap();
+        let app_state = AppState {
+            clock: Arc::new(clock::MockClock::new(
+                UNIX_EPOCH + Duration::from_secs(23 * 60 * 60),
+            )),
+            ..test_state(cfg)
+        };
+        let log = slog::Logger::root(slog::Discard, o!());
+        let filters = filter_config(&app_state.cfg, &log);
+
+        assert!(hold_for_quiet_hours(
+            &app_state,
+            &filters,
+            &Notification::default(),
+            &log
+        ));
+        assert!(app_state.held.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn quiet_hours_cap() {
+        let mut cfg = test_settings!();
+        cfg.set("filters.quiet_hours.start", "22:00").unwrap();
+        cfg.set("filters.quiet_hours.end", "07:00").unwrap();
+        cfg.set("service.max_held_events", 2).unwrap();
+        let app_state = AppState {
+            clock: Arc::new(clock::MockClock::new(
+                UNIX_EPOCH + Duration::from_secs(23 * 60 * 60),
+            )),
+            ..test_state(cfg)
+        };
+        let log = slog::Logger::root(slog::Discard, o!());
+        let filters = filter_config(&app_state.cfg, &log);
+        let push = |message: &str| Notification {
+            kind: String::from("push"),
+            message: String::from(message),
+            ..Notification::default()
+        };
+
+        for message in &["one", "two", "three"] {
+            assert!(hold_for_quiet_hours(
+                &app_state,
+                &filters,
+                &push(message),
+                &log
+            ));
+        }
+
+        let held = app_state
+            .held
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.message.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(held, vec![String::from("two"), String::from("three")]);
+    }
+
+    #[test]
+    fn gitlab_token_errors() {
+        let app_state = test_state(test_settings!());
+
+        let mut headers = HeaderMap::new();
+        match compare_gitlab_token(&headers, &app_state) {
+            Err(Error::Token(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        headers.insert("x-gitlab-token", HeaderValue::from_static("WRONG_TOKEN"));
+        match compare_gitlab_token(&headers, &app_state) {
+            Err(Error::Token(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        headers.insert(
+            "x-gitlab-token",
+            HeaderValue::from_bytes(b"TEST_TOKEN\xff").unwrap(),
+        );
+        match compare_gitlab_token(&headers, &app_state) {
+            Err(Error::Token(e)) => assert!(e.contains("UTF-8")),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        headers.insert("x-gitlab-token", HeaderValue::from_static("TEST_TOKEN"));
+        assert!(compare_gitlab_token(&headers, &app_state).is_ok());
+
+        // a token in the request but none configured
+        let app_state = test_state(config::Config::default());
+        match compare_gitlab_token(&headers, &app_state) {
+            Err(Error::Config(_)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn gitlab_without_token() {
+        let mut cfg = config::Config::default();
+        cfg.set("gitlab.require_token", false).unwrap();
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains("pushed"));
+    }
+
+    #[test]
+    fn gitlab_custom_token_header() {
+        let mut cfg = test_settings!();
+        cfg.set("gitlab.token_header", "X-Proxy-Token").unwrap();
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+
+        // the default header is not looked at anymore
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Proxy-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains("pushed"));
+    }
+
+    #[test]
+    fn gitlab_invalid_token() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn gitlab_invalid_token_body() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("WRONG_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(body["code"], 400);
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("mismatching gitlab token"));
+    }
+
+    #[test]
+    fn gitlab_parse_failure_body() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                r#"{"object_kind": "push"}"#,
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(body["code"], 400);
+        assert!(body["error"]["message"].is_string());
+    }
+
+    #[test]
+    fn gitlab_unknown_kind() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                r#"{"object_kind": "emoji"}"#,
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!irc.contains("emoji"));
+
+        let response = test_server
+            .client()
+            .get("http://localhost/metrics")
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert!(response
+            .read_utf8_body()
+            .unwrap()
+            .contains("unknown_events_total{kind=\"emoji\"} 1\n"));
+    }
+
+    #[test]
+    fn gitlab_notify_unknown() {
+        let irc = FakeIrcWriter::new();
+        let mut cfg = test_settings!();
+        cfg.set("filters.notify_unknown", true).unwrap();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                r#"{"object_kind": "emoji"}"#,
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains("❓ received unknown event: emoji"));
+    }
+
+    #[test]
+    fn gitlab_missing_kind() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let post = |body: &'static str| {
+            test_server
+                .client()
+                .post("http://localhost/gitlab/", body, mime::APPLICATION_JSON)
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap()
+        };
+
+        assert_eq!(post(r#"{"user_name": "alice"}"#).status(), StatusCode::OK);
+        let response = test_server
+            .client()
+            .get("http://localhost/metrics")
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert!(response
+            .read_utf8_body()
+            .unwrap()
+            .contains("unknown_events_total{kind=\"no object kind\"} 1\n"));
+
+        let response = post(r#"[{"object_kind": "push"}]"#);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(
+            body["error"]["message"],
+            "invalid input: event is not a JSON object"
+        );
+    }
+
+    #[test]
+    fn gitlab_invalid_json_body() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                "{ not json",
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(body["code"], 400);
+    }
+
+    #[test]
+    fn bind_backoff() {
+        let base = Duration::from_millis(500);
+        assert_eq!(bind_retry_delay(base, 0), Duration::from_millis(500));
+        assert_eq!(bind_retry_delay(base, 1), Duration::from_secs(1));
+        assert_eq!(bind_retry_delay(base, 3), Duration::from_secs(4));
+        assert_eq!(bind_retry_delay(base, 10), MAX_BIND_RETRY_DELAY);
+        assert_eq!(bind_retry_delay(base, 100), MAX_BIND_RETRY_DELAY);
+    }
+
+    #[test]
+    fn format_push_file() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/push.json");
+
+        let message = format_file(Some("push"), &file, &FormatConfig::default(), &log).unwrap();
+        assert!(message.contains("pushed"));
+        // the kind is taken from the event when not given
+        assert_eq!(
+            format_file(None, &file, &FormatConfig::default(), &log).unwrap(),
+            message
+        );
+
+        assert!(format_file(
+            Some("push"),
+            Path::new("does-not-exist.json"),
+            &FormatConfig::default(),
+            &log
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn check_config_files() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let path = std::env::temp_dir().join(format!("raccoon-check-{}.toml", std::process::id()));
+
+        std::fs::write(
+            &path,
+            r##"
+[gitlab]
+token = "TEST_TOKEN"
+
+[irc]
+nickname = "raccoon"
+nick_password = "secret"
+server = "irc.example.com"
+port = 6697
+channels = ["#chan"]
+"##,
+        )
+        .unwrap();
+        let cfg = read_config(&[path.clone()], &log).unwrap();
+        assert!(check_config(&cfg).is_empty());
+
+        std::fs::write(
+            &path,
+            r##"
+[service]
+backends = ["irc", "carrier-pigeon"]
+
+[irc]
+nickname = "raccoon"
+nick_password = "secret"
+server = "irc.example.com"
+port = 6697
+channels = ["#bad chan"]
+"##,
+        )
+        .unwrap();
+        let cfg = read_config(&[path.clone()], &log).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let problems = check_config(&cfg);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("gitlab.token"));
+        assert!(problems[1].contains("#bad chan"));
+        assert_eq!(problems[2], "unknown backend carrier-pigeon");
+    }
+
+    #[test]
+    fn layered_config_files() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let base = std::env::temp_dir().join(format!("raccoon-base-{}.toml", std::process::id()));
+        let host = std::env::temp_dir().join(format!("raccoon-host-{}.toml", std::process::id()));
+        std::fs::write(
+            &base,
+            "[gitlab]\ntoken = \"BASE_TOKEN\"\n\n[service]\nport = 8000\n",
+        )
+        .unwrap();
+        std::fs::write(&host, "[gitlab]\ntoken = \"HOST_TOKEN\"\n").unwrap();
+
+        let cfg = read_config(&[base.clone(), host.clone()], &log).unwrap();
+        std::fs::remove_file(&base).unwrap();
+        std::fs::remove_file(&host).unwrap();
+
+        assert_eq!(cfg.get_str("gitlab.token").unwrap(), "HOST_TOKEN");
+        assert_eq!(cfg.get_int("service.port").unwrap(), 8000);
+    }
+
+    #[test]
+    fn sync_logging() {
+        let mut cfg = config::Config::default();
+        assert_eq!(logging(&cfg), Logging::Async);
+        cfg.set("service.sync_logging", true).unwrap();
+        assert_eq!(logging(&cfg), Logging::Sync);
+
+        // mostly checks that both kinds of drains fit together
+        for mode in &[Logging::Async, Logging::Sync] {
+            info!(logger(*mode), "logging with {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn service_threads() {
+        let service = |threads: Option<i64>| {
+            let mut cfg = config::Config::default();
+            cfg.set("service.bind", "127.0.0.1").unwrap();
+            cfg.set("service.port", 7878).unwrap();
+            if let Some(t) = threads {
+                cfg.set("service.threads", t).unwrap();
+            }
+            cfg.get::<ServiceConfig>("service").unwrap().threads()
+        };
+
+        assert_eq!(service(None), num_cpus::get().min(MAX_THREADS));
+        assert_eq!(service(Some(4)), 4);
+        assert_eq!(service(Some(0)), 1);
+        assert_eq!(service(Some(100_000)), MAX_THREADS);
+    }
+
+    #[test]
+    fn service_tls() {
+        let service = |cert: Option<&str>, key: Option<&str>| {
+            let mut cfg = config::Config::default();
+            set_service_defaults(&mut cfg).unwrap();
+            if let Some(cert) = cert {
+                cfg.set("service.tls_cert", cert).unwrap();
+            }
+            if let Some(key) = key {
+                cfg.set("service.tls_key", key).unwrap();
+            }
+            cfg.get::<ServiceConfig>("service").unwrap()
+        };
+
+        // plain HTTP unless told otherwise
+        let plain = service(None, None);
+        assert_eq!(plain.scheme(), Ok(Scheme::Http));
+        assert!(plain.tls().unwrap().is_none());
+
+        let tls = service(Some("test/tls/cert.pem"), Some("test/tls/key.pem"));
+        assert_eq!(
+            tls.scheme(),
+            Ok(Scheme::Https {
+                cert: PathBuf::from("test/tls/cert.pem"),
+                key: PathBuf::from("test/tls/key.pem"),
+            })
+        );
+        assert!(tls.tls().unwrap().is_some());
+
+        assert!(service(Some("test/tls/cert.pem"), None).scheme().is_err());
+        assert!(service(None, Some("test/tls/key.pem")).tls().is_err());
+        assert!(
+            service(Some("test/tls/missing.pem"), Some("test/tls/key.pem"))
+                .tls()
+                .is_err()
+        );
+
+        assert_eq!(
+            startup_summary(&test_settings!(), &tls, "127.0.0.1:7878")[0],
+            "listening for requests at https://127.0.0.1:7878"
+        );
+        assert_eq!(
+            startup_summary(&test_settings!(), &plain, "127.0.0.1:7878")[0],
+            "listening for requests at http://127.0.0.1:7878"
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let mut cfg = test_settings!();
+        cfg.set("irc.server", "irc.server.org").unwrap();
+        cfg.set("irc.port", 6697).unwrap();
+        cfg.set("irc.nickname", "raccoon").unwrap();
+        cfg.set("irc.nick_password", "secret").unwrap();
+        cfg.set("irc.channels", vec!["#one", "#two:secret"])
+            .unwrap();
+        set_service_defaults(&mut cfg).unwrap();
+        let service = cfg.get::<ServiceConfig>("service").unwrap();
+
+        assert_eq!(
+            startup_summary(&cfg, &service, "127.0.0.1:7878"),
+            vec![
+                "listening for requests at http://127.0.0.1:7878",
+                "IRC server irc.server.org:6697 as raccoon",
+                "channels: #one, #two",
+                "event filters: none",
+                "backends: irc",
+            ]
+        );
+    }
+
+    #[test]
+    fn gitlab_push() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert!(irc.contains("pushed"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn gitlab_event_header() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+
+        // strip the object kind, only the header tells what this is
+        let mut body: serde_json::Value =
+            serde_json::from_str(include_str!("../test/push.json")).unwrap();
+        body.as_object_mut().unwrap().remove("object_kind");
+
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                body.to_string(),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .with_header("X-Gitlab-Event", HeaderValue::from_static("Push Hook"))
+            .perform()
+            .unwrap();
+
+        assert!(irc.contains("pushed"));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn gitlab_color_nicks() {
+        let irc = FakeIrcWriter::new();
+        let mut cfg = test_settings!();
+        cfg.set("format.color_nicks", true).unwrap();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains(&format!(
+            "\x03{:02}John Smith\x03",
+            format::nick_color("John Smith")
+        )));
+    }
+
+    #[test]
+    fn gitlab_token_file() {
+        let path = std::env::temp_dir().join(format!("raccoon-token-{}", std::process::id()));
+        std::fs::write(&path, "FILE_TOKEN\n").unwrap();
+
+        let mut cfg = test_settings!();
+        cfg.set("gitlab.token_file", path.to_str().unwrap())
+            .unwrap();
+        read_token_file(&mut cfg).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cfg.get_str("gitlab.token").unwrap(), "FILE_TOKEN");
+
+        // without a file there is nothing to read
+        let mut cfg = test_settings!();
+        read_token_file(&mut cfg).unwrap();
+        assert_eq!(cfg.get_str("gitlab.token").unwrap(), "TEST_TOKEN");
+
+        cfg.set("gitlab.token_file", path.to_str().unwrap())
+            .unwrap();
+        assert!(read_token_file(&mut cfg).is_err());
+    }
+
+    #[test]
+    fn gitlab_journal() {
+        let path = std::env::temp_dir().join(format!("raccoon-journal-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut cfg = test_settings!();
+        cfg.set("service.journal_path", path.to_str().unwrap())
+            .unwrap();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let entries = contents.lines().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+
+        let entry: serde_json::Value = serde_json::from_str(entries[0]).unwrap();
+        assert_eq!(entry["kind"], "push");
+        assert!(entry["message"].as_str().unwrap().contains("pushed"));
+        assert!(entry["timestamp"].is_u64());
+    }
+
+    #[test]
+    fn status() {
+        let irc = FakeIrcWriter {
+            channels: vec![String::from("#one"), String::from("#two")],
+            ..FakeIrcWriter::new()
+        };
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/status")
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let status: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(status["irc"]["connected"], true);
+        assert_eq!(status["irc"]["channels"], json!(["#one", "#two"]));
+        assert!(status["uptime_seconds"].is_u64());
+        assert!(status["started_at"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn last_event() {
+        // 2016-08-12 15:30:00 UTC
+        let clock = Arc::new(clock::MockClock::new(
+            UNIX_EPOCH + Duration::from_secs(1_471_015_800),
+        ));
+        let test_server = TestServer::new(
+            RouterBuilder::new()
+                .config(test_settings!())
+                .notifier(Box::new(FakeIrcWriter::new()))
+                .clock(clock.clone())
+                .build(),
+        )
+        .unwrap();
+        let last_event = |path: &str| {
+            let response = test_server
+                .client()
+                .get(path)
+                .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            let body: serde_json::Value =
+                serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+            body["last_event_at"].clone()
+        };
+
+        assert!(last_event("http://localhost/status").is_null());
+        assert!(last_event("http://localhost/health").is_null());
+
+        clock.advance(Duration::from_secs(60));
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                include_str!("../test/push.json"),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(last_event("http://localhost/status"), json!(1_471_015_860));
+        assert_eq!(last_event("http://localhost/health"), json!(1_471_015_860));
+    }
+
+    #[test]
+    fn health() {
+        let health = |irc: Box<irc::IrcWriter + Send>| {
+            let test_server = TestServer::new(router(
+                slog::Logger::root(slog::Discard, o!()),
+                test_settings!(),
+                irc,
+            ))
+            .unwrap();
+            let response = test_server
+                .client()
+                .get("http://localhost/health")
+                .perform()
+                .unwrap();
+            let status = response.status();
+            let body: serde_json::Value =
+                serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+            (status, body["status"].clone())
+        };
+
+        assert_eq!(
+            health(Box::new(FakeIrcWriter::new())),
+            (StatusCode::OK, json!("ok"))
+        );
+        // a writer that is not connected, like one waiting to reconnect
+        assert_eq!(
+            health(Box::new(FailingIrcWriter)),
+            (StatusCode::SERVICE_UNAVAILABLE, json!("degraded"))
+        );
+    }
+
+    #[test]
+    fn buffer_when_degraded() {
+        let post = |cfg: config::Config| {
+            let test_server = TestServer::new(router(
+                slog::Logger::root(slog::Discard, o!()),
+                cfg,
+                Box::new(FailingIrcWriter),
+            ))
+            .unwrap();
+            test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    include_str!("../test/push.json"),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap()
+                .status()
+        };
+
+        assert_eq!(post(test_settings!()), StatusCode::OK);
+        let mut cfg = test_settings!();
+        cfg.set("service.buffer_when_degraded", true).unwrap();
+        assert_eq!(post(cfg), StatusCode::ACCEPTED);
+
+        // held events stay until IRC is back
+        let app_state = AppState {
+            irc: Arc::new(Mutex::new(Box::new(FailingIrcWriter))),
+            ..test_state(test_settings!())
+        };
+        app_state.held.lock().unwrap().push(Notification::default());
+        release_held(&app_state.held, &app_state.irc, &app_state.logger);
+        assert_eq!(app_state.held.lock().unwrap().len(), 1);
+
+        // but no more than `service.max_held_events` of them
+        let mut cfg = test_settings!();
+        cfg.set("service.buffer_when_degraded", true).unwrap();
+        cfg.set("service.max_held_events", 3).unwrap();
+        let app_state = AppState {
+            irc: Arc::new(Mutex::new(Box::new(FailingIrcWriter))),
+            ..test_state(cfg)
+        };
+        for i in 0..5 {
+            let notification = Notification {
+                message: i.to_string(),
+                ..Notification::default()
+            };
+            assert!(hold_while_degraded(
+                &app_state,
+                &notification,
+                &app_state.logger
+            ));
+        }
+        let held = app_state
+            .held
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.message.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(held, vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn metrics() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/metrics")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = test_server
+            .client()
+            .get("http://localhost/metrics")
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.read_utf8_body().unwrap();
+        assert!(body.contains("# TYPE irc_messages_sent_total counter"));
+        assert!(body.contains("# TYPE irc_send_failures_total counter"));
+        assert!(body.contains("# TYPE gitlab_request_duration_seconds histogram"));
+    }
+
+    #[test]
+    fn uptime() {
+        let app_state = test_state(test_settings!());
+
+        let before = app_state.uptime();
+        thread::sleep(Duration::from_millis(10));
+        let after = app_state.uptime();
+        assert!(after > before);
+        assert!(after >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn recent_events() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+
+        for body in &[
+            include_str!("../test/push.json"),
+            include_str!("../test/issue.json"),
+        ] {
+            let response = test_server
+                .client()
+                .post("http://localhost/gitlab/", *body, mime::APPLICATION_JSON)
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = test_server
+            .client()
+            .get("http://localhost/events?limit=20")
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let events: serde_json::Value =
+            serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        let events = events.as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["kind"], "issue");
+        assert_eq!(events[1]["kind"], "push");
+        assert!(events[1]["message"].as_str().unwrap().contains("pushed"));
+    }
+
+    #[test]
+    fn recent_events_invalid_token() {
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/events")
+            .with_header("X-Admin-Token", HeaderValue::from_static("WRONG_TOKEN"))
+            .perform()
+            .unwrap();
 
-                    // determine kind and format message
-                    let json: serde_json::Value = json;
-                    let object_kind = json["object_kind"]
-                        .as_str()
-                        .unwrap_or("no object kind")
-                        .to_owned();
-                    let msg = gitlab::dispatch(
-                        &object_kind,
-                        json,
-                        &log.new(o!("object_kind" => object_kind.clone())),
-                    );
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
 
-                    // send message to irc
-                    match msg {
-                        Ok(m) => {
-                            debug!(log, "{}", m);
-                            if let Err(e) = app_state
-                                .irc
-                                .lock()
-                                .map_err(|_| String::from("failed to obtain irc writer lock"))
-                                .and_then(|mut i| i.write(&m))
-                            {
-                                error!(log, "failed to post message to IRC: {}", e);
-                            }
-                        }
-                        Err(e) => {
-                            let resp = create_response(
-                                &state,
-                                StatusCode::BAD_REQUEST,
-                                mime::APPLICATION_JSON,
-                                json!({
-                                    "code": 400,
-                                    "error": {
-                                        "message": format!("Failed to parse Gitlab payload: {}", e)
-                                    }
-                                })
-                                .to_string(),
-                            );
-                            return Ok((state, resp));
-                        }
-                    }
-                }
-                Err(e) => return Err((state, e.into_handler_error())),
-            }
+    #[test]
+    fn admin_replay() {
+        let irc = FakeIrcWriter::new();
+        let kinds = Arc::new(RwLock::new(Vec::new()));
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(NotifyingIrcWriter {
+                kinds: kinds.clone(),
+                irc: irc.clone(),
+            }),
+        ))
+        .unwrap();
 
-            // return value is only used to signal that we
-            // received the thing, so just send OK in case
-            // we got down here 🦆
-            let resp = create_empty_response(&state, StatusCode::OK);
-            Ok((state, resp))
+        for body in &[
+            include_str!("../test/push.json"),
+            include_str!("../test/issue.json"),
+        ] {
+            test_server
+                .client()
+                .post("http://localhost/gitlab/", *body, mime::APPLICATION_JSON)
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
         }
-        Err(e) => Err((state, e.into_handler_error())),
-    });
-
-    Box::new(f)
-}
-
-#[derive(StructOpt, Debug)]
-/// Raccoon is a service that accepts Gitlab HTTP hooks as described at
-/// https://docs.gitlab.com/ee/user/project/integrations/webhooks.html
-/// and sends the resulting formatted text to IRC.
-struct Opt {
-    #[structopt(parse(from_os_str), short = "c", long = "config")]
-    /// Config file to use. This overrides the standard config
-    /// file resolution. See man page for config file format and
-    /// resolution order if this parameter is not specified.
-    config: Option<PathBuf>,
-
-    #[structopt(short = "p", long = "port")]
-    /// Port to bind the service to, default is 7878.
-    /// Can also be set in the settings file with the setting `service.port`.
-    port: Option<u16>,
+        let sent = irc.buffer.read().unwrap().clone();
+        assert!(sent.contains("pushed"));
+        irc.buffer.write().unwrap().clear();
+        kinds.write().unwrap().clear();
 
-    #[structopt(short = "b", long = "bind")]
-    /// Address to bind the service to, default is 127.0.0.1.
-    /// Can also be set in the settings file with the setting `service.bind`.
-    bind: Option<String>,
-}
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/admin/replay?count=5",
+                "",
+                mime::TEXT_PLAIN,
+            )
+            .with_header("X-Admin-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
 
-#[derive(Deserialize, Debug)]
-struct ServiceConfig {
-    bind: String,
-    port: u16,
-}
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*irc.buffer.read().unwrap(), sent);
+        // replays go through notify, so they are routed like the first time
+        assert_eq!(*kinds.read().unwrap(), vec!["push", "issue"]);
+    }
 
-pub fn main() -> Result<(), String> {
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::FullFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+    #[test]
+    fn admin_replay_invalid_token() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post("http://localhost/admin/replay", "", mime::TEXT_PLAIN)
+            .with_header("X-Admin-Token", HeaderValue::from_static("WRONG_TOKEN"))
+            .perform()
+            .unwrap();
 
-    let log = slog::Logger::root(drain, o!());
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
 
-    let opt = Opt::from_args();
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
 
-    let mut cfg = config::Config::default();
-    match opt.config {
-        Some(c) => {
-            info!(
-                log,
-                "reading raccoon config file {} as specified on the command line",
-                c.display()
-            );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
 
-            cfg.merge(config::File::with_name(
-                c.to_str().unwrap_or("<invalid-string>"),
-            ))
-            .map_err(|e| {
-                error!(log, "failed to read config: {}", e);
-                e.to_string()
-            })?;
-        }
-        None => {
-            info!(log, "reading raccoon config file");
-            match xdg::BaseDirectories::with_prefix("raccoon") {
-                Ok(xdg_dirs) => {
-                    if let Some(f) = xdg_dirs.find_config_file("raccoon.toml") {
-                        info!(log, "using config file from {}", f.display());
-                        cfg.merge(config::File::with_name(
-                            f.to_str().unwrap_or("invalid-string"),
-                        ))
-                        .map_err(|e| format!("failed to parse cfg at {}: {}", f.display(), e))?;
-                    }
-                }
-                Err(e) => warn!(log, "failed to get XDG directories: {}", e),
-            };
+    #[test]
+    fn gitlab_gzip() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                gzip(include_bytes!("../test/push.json")),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .with_header("Content-Encoding", HeaderValue::from_static("gzip"))
+            .perform()
+            .unwrap();
 
-            if Path::new("./raccoon").exists() {
-                info!(log, "using config file in current directory");
-                cfg.merge(config::File::with_name("./raccoon"))
-                    .map_err(|e| {
-                        error!(log, "failed to read config: {}", e);
-                        e.to_string()
-                    })?;
-            }
-        }
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains("pushed"));
     }
 
-    cfg.merge(config::Environment::with_prefix("RACCOON"))
-        .map_err(|e| {
-            error!(log, "failed to read environment settings: {}", e);
-            e.to_string()
-        })?;
+    #[test]
+    fn gitlab_gzip_too_large() {
+        let mut cfg = test_settings!();
+        cfg.set("service.max_body_size", 1024).unwrap();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(FakeIrcWriter::new()),
+        ))
+        .unwrap();
 
-    info!(log, "connecting to IRC");
-    let writer = irc::RealIrcWriter::new(irc::init(&cfg, &log)?);
+        // compresses very well, but is too large once decompressed
+        let body = gzip(&vec![b' '; 64 * 1024]);
+        assert!(body.len() < 1024);
 
-    cfg.set_default("service.bind", "127.0.0.1".to_owned())
-        .map_err(|e| {
-            error!(
-                log,
-                "failed to set default value for service.bind setting: {}", e
-            );
-            e.to_string()
-        })?;
-    cfg.set_default("service.port", 7878).map_err(|e| {
-        error!(
-            log,
-            "failed to set default value for service.bind setting: {}", e
-        );
-        e.to_string()
-    })?;
+        let response = test_server
+            .client()
+            .post("http://localhost/gitlab/", body, mime::APPLICATION_JSON)
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .with_header("Content-Encoding", HeaderValue::from_static("gzip"))
+            .perform()
+            .unwrap();
 
-    let service_config: ServiceConfig = cfg.get("service").map_err(|e| {
-        error!(log, "failed to parse service settings: {}", e);
-        e.to_string()
-    })?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
 
-    let addr = format!(
-        "{}:{}",
-        opt.bind.unwrap_or(service_config.bind),
-        opt.port.unwrap_or(service_config.port)
-    );
+    fn post_merge_request(cfg: config::Config, body: &serde_json::Value) -> FakeIrcWriter {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            cfg,
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                body.to_string(),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    info!(log, "Listening for requests at http://{}", addr);
-    gotham::start(addr, router(log, cfg, Box::new(writer)));
+        irc
+    }
 
-    Ok(())
-}
+    #[test]
+    fn gitlab_suppress_draft_mr() {
+        let mut cfg = test_settings!();
+        cfg.set("filters.suppress_draft_mr", true).unwrap();
+
+        let mut body: serde_json::Value =
+            serde_json::from_str(include_str!("../test/merge_request.json")).unwrap();
+        body["object_attributes"]["title"] = json!("Draft: MS-Viewport");
+        body["object_attributes"]["action"] = json!("update");
+        cfg.set("filters.mr_actions", vec!["open", "update"])
+            .unwrap();
+        let irc = post_merge_request(cfg.clone(), &body);
+        assert!(irc.buffer.read().unwrap().is_empty());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use gotham::test::TestServer;
-    use hyper::{header::HeaderValue, StatusCode};
-    use mime;
+        // once ready, it is no longer a draft
+        body["object_attributes"]["title"] = json!("MS-Viewport");
+        let irc = post_merge_request(cfg, &body);
+        assert!(irc.contains("MS-Viewport"));
+    }
 
-    macro_rules! test_settings {
-        () => {{
-            let mut cfg = config::Config::default();
-            cfg.set("gitlab.token", "TEST_TOKEN").unwrap();
-            cfg
-        }};
+    #[test]
+    fn gitlab_issue_actions() {
+        let post_issue = |action: &str| {
+            let irc = FakeIrcWriter::new();
+            let test_server = TestServer::new(router(
+                slog::Logger::root(slog::Discard, o!()),
+                test_settings!(),
+                Box::new(irc.clone()),
+            ))
+            .unwrap();
+            let mut body: serde_json::Value =
+                serde_json::from_str(include_str!("../test/issue.json")).unwrap();
+            body["object_attributes"]["action"] = json!(action);
+            let response = test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    body.to_string(),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            irc
+        };
+
+        assert!(post_issue("open").contains("opened issue"));
+        assert!(post_issue("update").buffer.read().unwrap().is_empty());
     }
 
-    #[derive(Clone)]
-    pub struct FakeIrcWriter {
-        pub buffer: Arc<RwLock<String>>,
+    #[test]
+    fn gitlab_skip_ci() {
+        let irc = FakeIrcWriter::new();
+        let test_server = TestServer::new(router(
+            slog::Logger::root(slog::Discard, o!()),
+            test_settings!(),
+            Box::new(irc.clone()),
+        ))
+        .unwrap();
+        let mut body: serde_json::Value =
+            serde_json::from_str(include_str!("../test/push.json")).unwrap();
+        body["commits"][1]["message"] = json!("fixed readme [skip ci]");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/gitlab/",
+                body.to_string(),
+                mime::APPLICATION_JSON,
+            )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.buffer.read().unwrap().is_empty());
     }
 
-    impl FakeIrcWriter {
-        pub fn new() -> Self {
-            FakeIrcWriter {
-                buffer: Arc::new(RwLock::new(String::new())),
-            }
-        }
+    /// Takes its time with every message.
+    struct SlowIrcWriter(Duration);
 
-        pub fn contains(&self, sub: &str) -> bool {
-            let s = self.buffer.read().unwrap();
-            s.contains(sub)
+    impl irc::IrcWriter for SlowIrcWriter {
+        fn write(&mut self, _message: &str) -> Result<(), Error> {
+            thread::sleep(self.0);
+            Ok(())
         }
     }
 
-    impl irc::IrcWriter for FakeIrcWriter {
-        fn write(&mut self, message: &str) -> Result<(), String> {
-            let mut b = self.buffer.write().unwrap();
-            b.push_str(message);
+    /// Keeps the messages of everything logged.
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for Capture {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
             Ok(())
         }
     }
 
     #[test]
-    fn gitlab_invalid_token() {
+    fn slow_request_warning() {
+        let post_push = |delay_ms: u64| {
+            let logged = Arc::new(Mutex::new(Vec::new()));
+            let mut cfg = test_settings!();
+            cfg.set("service.slow_request_ms", 50).unwrap();
+            let test_server = TestServer::new(router(
+                slog::Logger::root(Capture(logged.clone()), o!()),
+                cfg,
+                Box::new(SlowIrcWriter(Duration::from_millis(delay_ms))),
+            ))
+            .unwrap();
+            let response = test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    include_str!("../test/push.json"),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let messages = logged.lock().unwrap();
+            messages
+                .iter()
+                .any(|m| m.contains("service.slow_request_ms"))
+        };
+
+        assert!(!post_push(0));
+        assert!(post_push(200));
+    }
+
+    #[test]
+    fn gitlab_request_timeout() {
+        let irc = FakeIrcWriter::new();
+        let mut cfg = test_settings!();
+        cfg.set("service.request_timeout_ms", 100).unwrap();
         let test_server = TestServer::new(router(
             slog::Logger::root(slog::Discard, o!()),
-            test_settings!(),
-            Box::new(FakeIrcWriter::new()),
+            cfg,
+            Box::new(irc.clone()),
         ))
         .unwrap();
+
+        // a client that never sends its body
+        let (_sender, body) = Body::channel();
+        let response = test_server
+            .client()
+            .post("http://localhost/gitlab/", body, mime::APPLICATION_JSON)
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(
+            response.headers().get(CONNECTION),
+            Some(&HeaderValue::from_static("close"))
+        );
+        assert!(irc.buffer.read().unwrap().is_empty());
+
+        // bodies arriving in time are handled as usual
         let response = test_server
             .client()
             .post(
@@ -334,20 +3192,21 @@ mod tests {
                 include_str!("../test/push.json"),
                 mime::APPLICATION_JSON,
             )
+            .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
             .perform()
             .unwrap();
-
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     #[test]
-    fn gitlab_push() {
+    fn router_builder() {
         let irc = FakeIrcWriter::new();
-        let test_server = TestServer::new(router(
-            slog::Logger::root(slog::Discard, o!()),
-            test_settings!(),
-            Box::new(irc.clone()),
-        ))
+        let test_server = TestServer::new(
+            RouterBuilder::new()
+                .config(test_settings!())
+                .notifier(Box::new(irc.clone()))
+                .build(),
+        )
         .unwrap();
         let response = test_server
             .client()
@@ -360,8 +3219,38 @@ mod tests {
             .perform()
             .unwrap();
 
-        assert!(irc.contains("pushed"));
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(irc.contains("pushed"));
+    }
+
+    #[test]
+    fn gitlab_ignore_users() {
+        let post_push = |ignore_users: Vec<&str>| {
+            let irc = FakeIrcWriter::new();
+            let mut cfg = test_settings!();
+            cfg.set("filters.ignore_users", ignore_users).unwrap();
+            let test_server = TestServer::new(router(
+                slog::Logger::root(slog::Discard, o!()),
+                cfg,
+                Box::new(irc.clone()),
+            ))
+            .unwrap();
+            let response = test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    include_str!("../test/push.json"),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            irc
+        };
+
+        assert!(post_push(vec!["*-bot"]).contains("pushed"));
+        assert!(post_push(vec!["js*"]).buffer.read().unwrap().is_empty());
     }
 
     #[test]
@@ -584,28 +3473,83 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
-    #[test]
-    fn gitlab_build() {
+    fn post_build(cfg: config::Config, status: &str) -> FakeIrcWriter {
         let irc = FakeIrcWriter::new();
         let test_server = TestServer::new(router(
             slog::Logger::root(slog::Discard, o!()),
-            test_settings!(),
+            cfg,
             Box::new(irc.clone()),
         ))
         .unwrap();
+        let mut body: serde_json::Value =
+            serde_json::from_str(include_str!("../test/build.json")).unwrap();
+        body["build_status"] = json!(status);
         let response = test_server
             .client()
             .post(
                 "http://localhost/gitlab/",
-                include_str!("../test/build.json"),
+                body.to_string(),
                 mime::APPLICATION_JSON,
             )
             .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
             .perform()
             .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        irc
+    }
+
+    #[test]
+    fn gitlab_build() {
+        let mut cfg = test_settings!();
+        cfg.set("filters.build_statuses", vec!["created"]).unwrap();
+        let irc = post_build(cfg, "created");
 
         assert!(irc.contains("Build"));
         assert!(irc.contains("created"));
-        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn gitlab_build_statuses() {
+        assert!(post_build(test_settings!(), "running")
+            .buffer
+            .read()
+            .unwrap()
+            .is_empty());
+        assert!(post_build(test_settings!(), "failed").contains("failed"));
+    }
+
+    #[test]
+    fn gitlab_pipeline_sources() {
+        let post_pipeline = |source: &str| {
+            let irc = FakeIrcWriter::new();
+            let mut cfg = test_settings!();
+            cfg.set("filters.pipeline_sources", vec!["push", "web"])
+                .unwrap();
+            let test_server = TestServer::new(router(
+                slog::Logger::root(slog::Discard, o!()),
+                cfg,
+                Box::new(irc.clone()),
+            ))
+            .unwrap();
+            let mut body: serde_json::Value =
+                serde_json::from_str(include_str!("../test/pipeline.json")).unwrap();
+            body["object_attributes"]["source"] = json!(source);
+            let response = test_server
+                .client()
+                .post(
+                    "http://localhost/gitlab/",
+                    body.to_string(),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("X-Gitlab-Token", HeaderValue::from_static("TEST_TOKEN"))
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            irc
+        };
+
+        assert!(post_pipeline("web").contains("Pipeline success (web)"));
+        assert!(post_pipeline("schedule").buffer.read().unwrap().is_empty());
     }
 }
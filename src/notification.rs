@@ -0,0 +1,199 @@
+use serde_json::Value;
+
+/// A formatted event, along with what is needed to decide where it goes.
+#[derive(Debug, Clone, Default)]
+pub struct Notification {
+    pub kind: String,
+    /// The environment of deployment events.
+    pub environment: Option<String>,
+    /// The branch or tag the event happened on, if any.
+    pub branch: Option<String>,
+    /// The default branch of the project, where Gitlab says so.
+    pub default_branch: Option<String>,
+    /// The status of pipelines, jobs and deployments.
+    pub status: Option<String>,
+    /// What started a pipeline, like `push` or `schedule`.
+    pub source: Option<String>,
+    /// The project, like `group/project` where Gitlab says so.
+    pub project: Option<String>,
+    /// What happened to issues and merge requests, like `open`.
+    pub action: Option<String>,
+    /// The username of who caused the event, or their name.
+    pub user: Option<String>,
+    pub message: String,
+}
+
+/// The path of `url`, like `group/project` for the web URL of a project.
+fn url_path(url: &str) -> Option<&str> {
+    let rest = url.splitn(2, "://").nth(1)?;
+    let path = rest.splitn(2, '/').nth(1)?.trim_end_matches('/');
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+impl Notification {
+    /// Picks out the routing information for an event of `kind`,
+    /// the message is filled in once it has been formatted.
+    pub fn new(kind: &str, data: &Value) -> Self {
+        let attrs = &data["object_attributes"];
+        let branch = data["ref"]
+            .as_str()
+            .or_else(|| attrs["ref"].as_str())
+            .or_else(|| attrs["target_branch"].as_str())
+            .map(|r| {
+                r.trim_start_matches("refs/heads/")
+                    .trim_start_matches("refs/tags/")
+                    .to_owned()
+            });
+        let status = attrs["status"]
+            .as_str()
+            .or_else(|| data["build_status"].as_str())
+            .or_else(|| data["status"].as_str())
+            .map(ToOwned::to_owned);
+        // group webhooks mix the events of many projects,
+        // so find the path of the project wherever the event has it
+        let project = data["project"]["path_with_namespace"]
+            .as_str()
+            .or_else(|| data["project_path_with_namespace"].as_str())
+            .or_else(|| data["path_with_namespace"].as_str())
+            .or_else(|| data["project_path"].as_str())
+            .or_else(|| data["repository"]["homepage"].as_str().and_then(url_path))
+            .or_else(|| data["project"]["name"].as_str())
+            .or_else(|| data["repository"]["name"].as_str())
+            .map(ToOwned::to_owned);
+        let default_branch = data["project"]["default_branch"]
+            .as_str()
+            .or_else(|| data["repository"]["default_branch"].as_str())
+            .map(ToOwned::to_owned);
+        let user = data["user"]["username"]
+            .as_str()
+            .or_else(|| data["user_username"].as_str())
+            .or_else(|| data["user"]["name"].as_str())
+            .or_else(|| data["user_name"].as_str())
+            .map(ToOwned::to_owned);
+
+        Notification {
+            kind: kind.to_owned(),
+            environment: data["environment"].as_str().map(ToOwned::to_owned),
+            branch,
+            default_branch,
+            status,
+            source: attrs["source"].as_str().map(ToOwned::to_owned),
+            project,
+            action: attrs["action"].as_str().map(ToOwned::to_owned),
+            user,
+            message: String::new(),
+        }
+    }
+
+    /// Whether the event happened on the default branch of its project,
+    /// which is only known if both were in the event.
+    pub fn on_default_branch(&self) -> Option<bool> {
+        match (&self.branch, &self.default_branch) {
+            (Some(branch), Some(default)) => Some(branch == default),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs::File;
+
+    #[test]
+    fn routing_information() {
+        let push = Notification::new("push", &json!({"ref": "refs/heads/main"}));
+        assert_eq!(push.branch.as_ref().map(String::as_str), Some("main"));
+        assert_eq!(push.status, None);
+
+        let pipeline = Notification::new(
+            "pipeline",
+            &json!({"object_attributes": {"ref": "main", "status": "failed"}}),
+        );
+        assert_eq!(pipeline.branch.as_ref().map(String::as_str), Some("main"));
+        assert_eq!(pipeline.status.as_ref().map(String::as_str), Some("failed"));
+        assert_eq!(pipeline.source, None);
+        let scheduled = Notification::new(
+            "pipeline",
+            &json!({"object_attributes": {"status": "success", "source": "schedule"}}),
+        );
+        assert_eq!(
+            scheduled.source.as_ref().map(String::as_str),
+            Some("schedule")
+        );
+
+        let issue = Notification::new(
+            "issue",
+            &json!({"project": {"name": "app", "path_with_namespace": "group/app"}}),
+        );
+        assert_eq!(
+            issue.project.as_ref().map(String::as_str),
+            Some("group/app")
+        );
+        assert_eq!(issue.action, None);
+        let update =
+            Notification::new("issue", &json!({"object_attributes": {"action": "update"}}));
+        assert_eq!(update.action.as_ref().map(String::as_str), Some("update"));
+        let build = Notification::new("build", &json!({"repository": {"name": "app"}}));
+        let job = Notification::new(
+            "build",
+            &json!({"repository": {"name": "app", "homepage": "http://gitlab.example.com/group/app"}}),
+        );
+        assert_eq!(job.project.as_ref().map(String::as_str), Some("group/app"));
+        assert_eq!(build.user, None);
+        assert_eq!(build.on_default_branch(), None);
+        assert_eq!(build.project.as_ref().map(String::as_str), Some("app"));
+        let push = Notification::new(
+            "push",
+            &json!({"user_name": "Deploy Bot", "user_username": "deploy-bot"}),
+        );
+        assert_eq!(push.user.as_ref().map(String::as_str), Some("deploy-bot"));
+        let issue = Notification::new("issue", &json!({"user": {"name": "Alice"}}));
+        assert_eq!(issue.user.as_ref().map(String::as_str), Some("Alice"));
+    }
+
+    #[test]
+    fn default_branch() {
+        let push: Value =
+            serde_json::from_reader(File::open("test/push.json").expect("find file")).unwrap();
+        let notification = Notification::new("push", &push);
+        assert_eq!(
+            notification.default_branch.as_ref().map(String::as_str),
+            Some("master")
+        );
+        assert_eq!(notification.on_default_branch(), Some(true));
+
+        let tag: Value =
+            serde_json::from_reader(File::open("test/push_tag.json").expect("find file")).unwrap();
+        assert_eq!(
+            Notification::new("tag_push", &tag).on_default_branch(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn project_of_every_event() {
+        for entry in std::fs::read_dir("test").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |e| e != "json") {
+                continue;
+            }
+            let data: Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+            let kind = data["object_kind"]
+                .as_str()
+                .or_else(|| data["event_name"].as_str())
+                .unwrap();
+            let project = Notification::new(kind, &data).project;
+            assert!(
+                project.map_or(false, |p| p.contains('/')),
+                "no project path in {}",
+                path.display()
+            );
+        }
+    }
+}
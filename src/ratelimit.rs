@@ -0,0 +1,325 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimit {
+    /// Number of messages allowed per interval.
+    pub messages: u32,
+    /// Length of the interval in seconds.
+    pub seconds: u64,
+    /// Maximum number of messages waiting to be sent per channel.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_queue_size() -> usize {
+    32
+}
+
+/// A classic token bucket: holds at most `capacity` tokens
+/// and refills continuously at `capacity` tokens per `interval`.
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, interval: Duration, now: Instant) -> Self {
+        let capacity = f64::from(capacity);
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: capacity / interval.as_secs_f64().max(0.001),
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.last {
+            let elapsed = now.duration_since(self.last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+            self.last = now;
+        }
+    }
+
+    /// Takes a token if one is available at `now`.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    /// The message can be sent right away.
+    Send,
+    /// The message was queued and will be returned by a later `drain`.
+    Queued,
+    /// The queue for the channel is full and the message was dropped.
+    Dropped,
+}
+
+struct ChannelState {
+    bucket: TokenBucket,
+    queue: VecDeque<String>,
+}
+
+/// The bucket shared by all channels, queueing `(channel, message)` pairs.
+struct GlobalState {
+    bucket: TokenBucket,
+    queue: VecDeque<(String, String)>,
+    queue_size: usize,
+}
+
+/// Keeps one token bucket and one bounded message queue per channel,
+/// and optionally one more of each for the messages of all channels.
+pub struct Limiter {
+    limit: Option<RateLimit>,
+    channels: HashMap<String, ChannelState>,
+    global_limit: Option<RateLimit>,
+    global: Option<GlobalState>,
+}
+
+impl Limiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Limiter {
+            limit: Some(limit),
+            channels: HashMap::new(),
+            global_limit: None,
+            global: None,
+        }
+    }
+
+    /// Only limits the total number of messages, not those per channel.
+    pub fn global(limit: RateLimit) -> Self {
+        Limiter {
+            limit: None,
+            channels: HashMap::new(),
+            global_limit: Some(limit),
+            global: None,
+        }
+    }
+
+    /// Also caps the total number of messages across all channels.
+    pub fn with_global(mut self, limit: RateLimit) -> Self {
+        self.global_limit = Some(limit);
+        self
+    }
+
+    fn channel(&mut self, channel: &str, now: Instant) -> Option<&mut ChannelState> {
+        let limit = self.limit.as_ref()?;
+        Some(
+            self.channels
+                .entry(channel.to_owned())
+                .or_insert_with(|| ChannelState {
+                    bucket: TokenBucket::new(
+                        limit.messages,
+                        Duration::from_secs(limit.seconds),
+                        now,
+                    ),
+                    queue: VecDeque::new(),
+                }),
+        )
+    }
+
+    fn global_state(&mut self, now: Instant) -> Option<&mut GlobalState> {
+        let limit = self.global_limit.as_ref()?;
+        Some(self.global.get_or_insert_with(|| GlobalState {
+            bucket: TokenBucket::new(limit.messages, Duration::from_secs(limit.seconds), now),
+            queue: VecDeque::new(),
+            queue_size: limit.queue_size,
+        }))
+    }
+
+    /// Decides what to do with `message` for `channel` at `now`.
+    pub fn submit(&mut self, channel: &str, message: &str, now: Instant) -> Admission {
+        // set up the global bucket before anything ends up in a channel queue
+        self.global_state(now);
+
+        let queue_size = self.limit.as_ref().map_or(0, |l| l.queue_size);
+        if let Some(state) = self.channel(channel, now) {
+            // keep ordering intact, nothing jumps ahead of already queued messages
+            if !state.queue.is_empty() || !state.bucket.try_take(now) {
+                return if state.queue.len() < queue_size {
+                    state.queue.push_back(message.to_owned());
+                    Admission::Queued
+                } else {
+                    Admission::Dropped
+                };
+            }
+        }
+
+        match self.global_state(now) {
+            None => Admission::Send,
+            Some(global) => {
+                if global.queue.is_empty() && global.bucket.try_take(now) {
+                    Admission::Send
+                } else if global.queue.len() < global.queue_size {
+                    global
+                        .queue
+                        .push_back((channel.to_owned(), message.to_owned()));
+                    Admission::Queued
+                } else {
+                    Admission::Dropped
+                }
+            }
+        }
+    }
+
+    /// Returns the queued `(channel, message)` pairs that may be sent at `now`.
+    pub fn drain(&mut self, now: Instant) -> Vec<(String, String)> {
+        let mut ready = Vec::new();
+        if let Some(ref mut global) = self.global {
+            while !global.queue.is_empty() && global.bucket.try_take(now) {
+                if let Some(m) = global.queue.pop_front() {
+                    ready.push(m);
+                }
+            }
+        }
+
+        let global = &mut self.global;
+        for (chan, state) in self.channels.iter_mut() {
+            // only let messages out of the channel queue if the global one has room
+            while !state.queue.is_empty()
+                && global
+                    .as_ref()
+                    .map_or(true, |g| g.queue.len() < g.queue_size)
+                && state.bucket.try_take(now)
+            {
+                if let Some(m) = state.queue.pop_front() {
+                    match global.as_mut() {
+                        Some(g) => {
+                            if g.queue.is_empty() && g.bucket.try_take(now) {
+                                ready.push((chan.clone(), m))
+                            } else {
+                                g.queue.push_back((chan.clone(), m))
+                            }
+                        }
+                        None => ready.push((chan.clone(), m)),
+                    }
+                }
+            }
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(messages: u32, seconds: u64, queue_size: usize) -> RateLimit {
+        RateLimit {
+            messages,
+            seconds,
+            queue_size,
+        }
+    }
+
+    #[test]
+    fn bucket_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, Duration::from_secs(3), start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+    }
+
+    #[test]
+    fn bucket_steady_state() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(2), start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+
+        // one token per second once the burst is used up
+        for i in 1..5 {
+            let now = start + Duration::from_secs(i);
+            assert!(bucket.try_take(now));
+            assert!(!bucket.try_take(now));
+        }
+
+        // never refills beyond capacity
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn limiter_queues_and_drains() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new(limit(1, 1, 2));
+
+        assert_eq!(limiter.submit("#chan", "one", start), Admission::Send);
+        assert_eq!(limiter.submit("#chan", "two", start), Admission::Queued);
+        assert_eq!(limiter.submit("#chan", "three", start), Admission::Queued);
+        assert_eq!(limiter.submit("#chan", "four", start), Admission::Dropped);
+
+        // other channels have their own bucket
+        assert_eq!(limiter.submit("#other", "one", start), Admission::Send);
+
+        assert!(limiter.drain(start).is_empty());
+        assert_eq!(
+            limiter.drain(start + Duration::from_secs(1)),
+            vec![(String::from("#chan"), String::from("two"))]
+        );
+        assert_eq!(
+            limiter.drain(start + Duration::from_secs(2)),
+            vec![(String::from("#chan"), String::from("three"))]
+        );
+    }
+
+    #[test]
+    fn global_limit() {
+        let start = Instant::now();
+        let mut limiter = Limiter::global(limit(2, 2, 1));
+
+        // shared by all channels, each of which would be fine on its own
+        assert_eq!(limiter.submit("#one", "a", start), Admission::Send);
+        assert_eq!(limiter.submit("#two", "b", start), Admission::Send);
+        assert_eq!(limiter.submit("#three", "c", start), Admission::Queued);
+        assert_eq!(limiter.submit("#four", "d", start), Admission::Dropped);
+
+        assert!(limiter.drain(start).is_empty());
+        assert_eq!(
+            limiter.drain(start + Duration::from_secs(1)),
+            vec![(String::from("#three"), String::from("c"))]
+        );
+    }
+
+    #[test]
+    fn global_and_channel_limits() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new(limit(1, 1, 2)).with_global(limit(2, 1, 2));
+
+        assert_eq!(limiter.submit("#one", "a", start), Admission::Send);
+        // held back by the channel limit
+        assert_eq!(limiter.submit("#one", "b", start), Admission::Queued);
+        assert_eq!(limiter.submit("#two", "c", start), Admission::Send);
+        // held back by the global limit
+        assert_eq!(limiter.submit("#three", "d", start), Admission::Queued);
+
+        // the global queue goes first, then the channel queues
+        assert_eq!(
+            limiter.drain(start + Duration::from_secs(1)),
+            vec![
+                (String::from("#three"), String::from("d")),
+                (String::from("#one"), String::from("b")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::glob;
+use crate::notification::Notification;
+
+/// Matches any value of a part of a routing key.
+const WILDCARD: &str = "*";
+
+/// Channels by routing key, where keys are built from a template
+/// like `{object_kind}:{project}` and parts of them may be `*`
+/// or patterns like `*-api`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RouteTable {
+    /// Template of the key to look up, with the parts separated by `:`.
+    pub key: String,
+    #[serde(default)]
+    pub channels: HashMap<String, Vec<String>>,
+}
+
+impl RouteTable {
+    /// The channels for `notification`, from the most specific matching route.
+    pub fn lookup(&self, notification: &Notification) -> Option<&Vec<String>> {
+        // keys are lowercased when the configuration is read
+        let key = render_key(&self.key, notification).to_lowercase();
+        let parts = key.split(':').collect::<Vec<_>>();
+        self.channels
+            .iter()
+            .filter_map(|(route, channels)| {
+                specificity(route, &parts).map(|s| ((s, route), channels))
+            })
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, channels)| channels)
+    }
+}
+
+/// Fills in the fields of `notification` in `template`, leaving
+/// out those that the notification does not have.
+pub fn render_key(template: &str, notification: &Notification) -> String {
+    let field = |value: &Option<String>| value.clone().unwrap_or_default();
+    template
+        .replace("{object_kind}", &notification.kind)
+        .replace("{project}", &field(&notification.project))
+        .replace("{branch}", &field(&notification.branch))
+        .replace("{environment}", &field(&notification.environment))
+        .replace("{status}", &field(&notification.status))
+}
+
+/// How specific `route` is for a key made of `parts`, or `None` if it does
+/// not match. Smaller is more specific: routes with fewer parts that are
+/// wildcards come first, those with later parts wildcarded before those
+/// with earlier ones, then parts with patterns like `*-api` and finally
+/// routes with more literal characters. A lone wildcard comes last.
+fn specificity(route: &str, parts: &[&str]) -> Option<(u32, u64, usize, Reverse<usize>)> {
+    if route == WILDCARD {
+        return Some((std::u32::MAX, std::u64::MAX, std::usize::MAX, Reverse(0)));
+    }
+
+    let route_parts = route.split(':').collect::<Vec<_>>();
+    if route_parts.len() != parts.len()
+        || !route_parts
+            .iter()
+            .zip(parts)
+            .all(|(r, p)| glob::matches(r, p))
+    {
+        return None;
+    }
+
+    // bit i of the mask wildcards the i-th part from the end
+    let n = route_parts.len();
+    let mask = route_parts
+        .iter()
+        .enumerate()
+        .filter(|(i, r)| **r == WILDCARD && n - 1 - i < 64)
+        .fold(0u64, |m, (i, _)| m | 1 << (n - 1 - i));
+    let patterns = route_parts
+        .iter()
+        .filter(|r| **r != WILDCARD && glob::is_pattern(r))
+        .count();
+    let literal = route.chars().filter(|c| *c != '*' && *c != '?').count();
+    Some((mask.count_ones(), mask, patterns, Reverse(literal)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(routes: &[(&str, &str)]) -> RouteTable {
+        RouteTable {
+            key: String::from("{object_kind}:{project}"),
+            channels: routes
+                .iter()
+                .map(|(k, c)| (String::from(*k), vec![String::from(*c)]))
+                .collect(),
+        }
+    }
+
+    fn event(kind: &str, project: &str) -> Notification {
+        Notification {
+            kind: String::from(kind),
+            project: Some(String::from(project)),
+            ..Notification::default()
+        }
+    }
+
+    fn channel<'a>(table: &'a RouteTable, kind: &str, project: &str) -> Option<&'a str> {
+        table.lookup(&event(kind, project)).map(|c| c[0].as_str())
+    }
+
+    #[test]
+    fn key_from_fields() {
+        assert_eq!(
+            render_key("{object_kind}:{project}", &event("push", "group/app")),
+            "push:group/app"
+        );
+        assert_eq!(
+            render_key(
+                "{object_kind}:{branch}",
+                &Notification {
+                    kind: String::from("push"),
+                    ..Notification::default()
+                }
+            ),
+            "push:"
+        );
+    }
+
+    #[test]
+    fn specificity_order() {
+        let parts = ["push", "app"];
+        let mut routes = vec!["*", "*:*", "*:app", "push:*", "push:app"];
+        routes.sort_by_key(|r| specificity(r, &parts));
+        assert_eq!(routes, vec!["push:app", "push:*", "*:app", "*:*", "*"]);
+
+        let mut routes = vec!["push:*", "push:a*", "push:ap?"];
+        routes.sort_by_key(|r| specificity(r, &parts));
+        assert_eq!(routes, vec!["push:ap?", "push:a*", "push:*"]);
+
+        assert_eq!(specificity("push", &parts), None);
+        assert_eq!(specificity("push:b*", &parts), None);
+    }
+
+    #[test]
+    fn exact_match() {
+        let routes = table(&[("push:app", "#app-pushes"), ("push:*", "#pushes")]);
+        assert_eq!(channel(&routes, "push", "app"), Some("#app-pushes"));
+    }
+
+    #[test]
+    fn partial_wildcards() {
+        let routes = table(&[("push:*", "#pushes"), ("*:app", "#app"), ("*", "#all")]);
+        assert_eq!(channel(&routes, "push", "other"), Some("#pushes"));
+        assert_eq!(channel(&routes, "issue", "app"), Some("#app"));
+        // the kind is more specific than the project
+        assert_eq!(channel(&routes, "push", "app"), Some("#pushes"));
+    }
+
+    #[test]
+    fn full_wildcard() {
+        let routes = table(&[("push:*", "#pushes"), ("*", "#all")]);
+        assert_eq!(channel(&routes, "issue", "app"), Some("#all"));
+        assert_eq!(
+            channel(&table(&[("push:*", "#pushes")]), "issue", "app"),
+            None
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        let routes = table(&[("push:*-api", "#apis"), ("push:*", "#pushes")]);
+        assert_eq!(channel(&routes, "push", "billing-api"), Some("#apis"));
+        assert_eq!(channel(&routes, "push", "website"), Some("#pushes"));
+
+        let routes = table(&[("*:group/*", "#group"), ("push:*", "#pushes")]);
+        assert_eq!(channel(&routes, "push", "group/app"), Some("#pushes"));
+        assert_eq!(channel(&routes, "issue", "group/app"), Some("#group"));
+        assert_eq!(channel(&routes, "issue", "other/app"), None);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let routes = table(&[("push:group/app", "#app")]);
+        assert_eq!(channel(&routes, "push", "Group/App"), Some("#app"));
+    }
+}
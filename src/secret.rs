@@ -0,0 +1,127 @@
+use std::{fmt, fs, path::Path};
+
+use crate::error::Error;
+
+/// Shown instead of secrets.
+const REDACTED: &str = "***";
+
+/// A password or token, which is never shown when formatted.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: String) -> Self {
+        Secret(secret)
+    }
+
+    /// The secret itself, for handing it to whoever needs to know.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Keys of secrets in the configuration, besides the nick passwords,
+/// which may also be found under `irc.networks`, and webhook headers.
+const SECRET_KEYS: &[&str] = &["gitlab.token", "admin.token", "irc.nick_password"];
+
+/// Secret values found in `cfg`, whether they parse or not.
+fn secrets(cfg: &config::Config) -> Vec<String> {
+    let mut secrets = SECRET_KEYS
+        .iter()
+        .filter_map(|k| cfg.get_str(k).ok())
+        .collect::<Vec<_>>();
+
+    if let Ok(networks) = cfg.get_array("irc.networks") {
+        secrets.extend(
+            networks
+                .into_iter()
+                .filter_map(|n| n.into_table().ok())
+                .filter_map(|mut n| n.remove("nick_password"))
+                .filter_map(|p| p.into_str().ok()),
+        );
+    }
+
+    // headers are where webhooks take their tokens
+    if let Ok(headers) = cfg.get_table("webhook.headers") {
+        secrets.extend(headers.into_iter().filter_map(|(_, v)| v.into_str().ok()));
+    }
+
+    secrets.retain(|s| !s.is_empty());
+    secrets
+}
+
+/// Hides every secret value of `cfg` in `message`, for
+/// errors that may quote the configuration they come from.
+pub fn redact(message: &str, cfg: &config::Config) -> String {
+    secrets(cfg)
+        .iter()
+        .fold(message.to_owned(), |m, s| m.replace(s.as_str(), REDACTED))
+}
+
+/// Reads a secret kept in a file of its own, like a token or a password,
+/// without the surrounding whitespace editors tend to leave behind.
+pub fn read(path: &Path) -> Result<String, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("failed to read {}: {}", path.display(), e)))?;
+
+    let secret = contents.trim();
+    if secret.is_empty() {
+        Err(Error::Config(format!("{} is empty", path.display())))
+    } else {
+        Ok(secret.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_hides_secrets() {
+        let secret = Secret::new(String::from("hunter2"));
+        assert_eq!(format!("{} {:?}", secret, secret), "*** ***");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn redact_secrets() {
+        let mut cfg = config::Config::default();
+        cfg.set("gitlab.token", "gl-token").unwrap();
+        cfg.set("irc.nick_password", "hunter2").unwrap();
+        cfg.set("webhook.headers.authorization", "Bearer hook")
+            .unwrap();
+
+        assert_eq!(
+            redact("gl-token and hunter2, but not hunter3", &cfg),
+            "*** and ***, but not hunter3"
+        );
+        assert_eq!(redact("sent Bearer hook", &cfg), "sent ***");
+    }
+
+    #[test]
+    fn read_secret() {
+        let path = std::env::temp_dir().join(format!("raccoon-secret-{}", std::process::id()));
+
+        fs::write(&path, "  hunter2\n").unwrap();
+        assert_eq!(read(&path).unwrap(), "hunter2");
+
+        fs::write(&path, "\n").unwrap();
+        assert!(read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(read(&path).is_err());
+    }
+}
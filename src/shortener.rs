@@ -0,0 +1,230 @@
+use futures::{future::Future, stream::Stream};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request, Uri};
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::error::Error;
+
+/// How long to wait for the shortener before using the long URL.
+const SHORTEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most URLs to remember before starting over.
+const CACHE_SIZE: usize = 1024;
+
+/// Turns long URLs into short ones.
+pub trait Shortener {
+    fn shorten(&self, url: &str) -> Result<String, Error>;
+}
+
+/// Leaves URLs as they are, used when no shortener is configured.
+pub struct NoopShortener;
+
+impl Shortener for NoopShortener {
+    fn shorten(&self, url: &str) -> Result<String, Error> {
+        Ok(url.to_owned())
+    }
+}
+
+/// POSTs the long URL as a plain text body to `endpoint`
+/// and expects the short URL as the response body.
+pub struct HttpShortener {
+    endpoint: Uri,
+}
+
+impl Shortener for HttpShortener {
+    fn shorten(&self, url: &str) -> Result<String, Error> {
+        let req = Request::post(self.endpoint.clone())
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(url.to_owned()))
+            .map_err(|e| Error::Shorten(e.to_string()))?;
+
+        // handlers already run on a runtime, so do the request on a separate one
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let client = Client::builder().keep_alive(false).build_http::<Body>();
+            hyper::rt::run(
+                client
+                    .request(req)
+                    .and_then(|res| {
+                        let status = res.status();
+                        res.into_body().concat2().map(move |b| (status, b))
+                    })
+                    .then(move |res| {
+                        let _ = tx.send(res);
+                        Ok(())
+                    }),
+            );
+        });
+
+        let (status, body) = rx
+            .recv_timeout(SHORTEN_TIMEOUT)
+            .map_err(|_| Error::Shorten(String::from("timed out")))?
+            .map_err(|e| Error::Shorten(e.to_string()))?;
+
+        let short = String::from_utf8_lossy(&body).trim().to_owned();
+        if !status.is_success() || short.is_empty() {
+            return Err(Error::Shorten(format!("unexpected response {}", status)));
+        }
+
+        Ok(short)
+    }
+}
+
+/// Remembers what `inner` said about each URL.
+pub struct CachingShortener<S> {
+    inner: S,
+    cache: Mutex<HashMap<String, String>>,
+}
+
+impl<S: Shortener> CachingShortener<S> {
+    pub fn new(inner: S) -> Self {
+        CachingShortener {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S: Shortener> Shortener for CachingShortener<S> {
+    fn shorten(&self, url: &str) -> Result<String, Error> {
+        if let Some(short) = self
+            .cache
+            .lock()
+            .map_err(|_| Error::Lock("shortener cache"))?
+            .get(url)
+        {
+            return Ok(short.clone());
+        }
+
+        let short = self.inner.shorten(url)?;
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| Error::Lock("shortener cache"))?;
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(url.to_owned(), short.clone());
+
+        Ok(short)
+    }
+}
+
+/// Creates the shortener configured under `shortener.endpoint`,
+/// or one that does nothing if there is none.
+pub fn from_config(cfg: &config::Config) -> Result<Box<Shortener + Send + Sync>, Error> {
+    match cfg.get_str("shortener.endpoint") {
+        Ok(endpoint) => {
+            let endpoint = endpoint
+                .parse::<Uri>()
+                .map_err(|e| Error::Config(format!("invalid shortener endpoint: {}", e)))?;
+            Ok(Box::new(CachingShortener::new(HttpShortener { endpoint })))
+        }
+        Err(config::ConfigError::NotFound(_)) => Ok(Box::new(NoopShortener)),
+        Err(e) => Err(Error::Config(format!(
+            "failed to parse shortener config: {}",
+            e
+        ))),
+    }
+}
+
+/// Start of the next URL in `s`.
+fn find_url(s: &str) -> Option<usize> {
+    match (s.find("http://"), s.find("https://")) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Replaces every URL in `message` with its short version. URLs
+/// that fail to shorten are left alone.
+pub fn shorten_urls(message: &str, shortener: &Shortener, logger: &slog::Logger) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = find_url(rest) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == ')')
+            .unwrap_or_else(|| rest.len());
+        // punctuation after a URL is most likely not part of it
+        let url = rest[..end].trim_end_matches(|c| ",.:;".contains(c));
+
+        match shortener.shorten(url) {
+            Ok(short) => out.push_str(&short),
+            Err(e) => {
+                warn!(logger, "failed to shorten {}: {}", url, e);
+                out.push_str(url);
+            }
+        }
+        rest = &rest[url.len()..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shortens to a counter and remembers how often it was asked.
+    struct MockShortener {
+        calls: Mutex<u32>,
+    }
+
+    impl Shortener for MockShortener {
+        fn shorten(&self, _url: &str) -> Result<String, Error> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            Ok(format!("https://sho.rt/{}", calls))
+        }
+    }
+
+    #[test]
+    fn substitutes_and_caches() {
+        let shortener = CachingShortener::new(MockShortener {
+            calls: Mutex::new(0),
+        });
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        let s = shorten_urls(
+            "💬 Alice commented on commit http://example.com/a/b/commit/cafe#note_1: nice (https://example.com/a/b)",
+            &shortener,
+            &log,
+        );
+        assert_eq!(
+            s,
+            "💬 Alice commented on commit https://sho.rt/1: nice (https://sho.rt/2)"
+        );
+
+        // the same URLs again, nothing new is asked for
+        let s = shorten_urls(
+            "see http://example.com/a/b/commit/cafe#note_1",
+            &shortener,
+            &log,
+        );
+        assert_eq!(s, "see https://sho.rt/1");
+        assert_eq!(*shortener.inner.calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn noop_without_endpoint() {
+        let shortener = from_config(&config::Config::default()).unwrap();
+        let log = slog::Logger::root(slog::Discard, o!());
+
+        assert_eq!(
+            shorten_urls("push to http://example.com/a/b", &*shortener, &log),
+            "push to http://example.com/a/b"
+        );
+        assert_eq!(
+            shorten_urls("no links here", &*shortener, &log),
+            "no links here"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+/// Number of received events per object kind.
+#[derive(Default)]
+pub struct EventCounters {
+    counts: BTreeMap<String, u64>,
+}
+
+impl EventCounters {
+    pub fn increment(&mut self, kind: &str) {
+        *self.counts.entry(kind.to_owned()).or_insert(0) += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.counts.clear();
+    }
+
+    /// A single line like `events: issue=3 push=12`.
+    pub fn summary(&self) -> String {
+        if self.counts.is_empty() {
+            return String::from("events: none");
+        }
+
+        let counts = self
+            .counts
+            .iter()
+            .map(|(kind, count)| format!("{}={}", kind, count))
+            .collect::<Vec<_>>();
+        format!("events: {}", counts.join(" "))
+    }
+
+    /// The counts in the Prometheus text format, as counter `name` labelled with the kind.
+    pub fn prometheus(&self, name: &str) -> String {
+        prometheus_counter(name, "kind", &self.counts)
+    }
+}
+
+/// Messages sent to each channel and attempts to send that failed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeliveryCounters {
+    pub sent: BTreeMap<String, u64>,
+    pub failed: BTreeMap<String, u64>,
+}
+
+impl DeliveryCounters {
+    pub fn record(&mut self, channel: &str, ok: bool) {
+        let counts = if ok { &mut self.sent } else { &mut self.failed };
+        *counts.entry(channel.to_owned()).or_insert(0) += 1;
+    }
+
+    /// Adds the counts of `other`, e.g. those of another network.
+    pub fn merge(&mut self, other: DeliveryCounters) {
+        add(&mut self.sent, other.sent);
+        add(&mut self.failed, other.failed);
+    }
+
+    /// The counters in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        prometheus_counter("irc_messages_sent_total", "channel", &self.sent)
+            + &prometheus_counter("irc_send_failures_total", "channel", &self.failed)
+    }
+}
+
+fn add(counts: &mut BTreeMap<String, u64>, more: BTreeMap<String, u64>) {
+    for (channel, n) in more {
+        *counts.entry(channel).or_insert(0) += n;
+    }
+}
+
+/// A counter called `name` with one sample per entry of `counts`, labelled with `label`.
+pub fn prometheus_counter(name: &str, label: &str, counts: &BTreeMap<String, u64>) -> String {
+    let mut out = format!("# TYPE {} counter\n", name);
+    for (value, count) in counts {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        out.push_str(&format!("{}{{{}=\"{}\"}} {}\n", name, label, value, count));
+    }
+    out
+}
+
+/// Upper bounds of the buckets of `RequestTimes`, in milliseconds.
+const REQUEST_TIME_BUCKETS_MS: [u64; 9] = [10, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// How long requests took to handle, counted in buckets like a Prometheus histogram.
+#[derive(Debug, Default, Clone)]
+pub struct RequestTimes {
+    /// Requests that took at most as long as each bucket says.
+    buckets: [u64; 9],
+    count: u64,
+    sum_ms: u64,
+}
+
+impl RequestTimes {
+    pub fn observe(&mut self, took: Duration) {
+        let ms = took.as_secs() * 1000 + u64::from(took.subsec_millis());
+        for (bucket, bound) in self.buckets.iter_mut().zip(&REQUEST_TIME_BUCKETS_MS) {
+            if ms <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+
+    /// The times in the Prometheus text format, as histogram `name` in seconds.
+    pub fn prometheus(&self, name: &str) -> String {
+        let mut out = format!("# TYPE {} histogram\n", name);
+        for (count, bound) in self.buckets.iter().zip(&REQUEST_TIME_BUCKETS_MS) {
+            out.push_str(&format!(
+                "{}_bucket{{le=\"{}\"}} {}\n",
+                name,
+                *bound as f64 / 1000.0,
+                count
+            ));
+        }
+        out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, self.count));
+        out.push_str(&format!("{}_sum {}\n", name, self.sum_ms as f64 / 1000.0));
+        out.push_str(&format!("{}_count {}\n", name, self.count));
+        out
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatsConfig {
+    /// Minutes between two summaries.
+    pub interval_minutes: u64,
+    /// Start counting from zero after every summary.
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Logs a summary every interval until the counters go away.
+pub fn log_periodically(counters: Weak<Mutex<EventCounters>>, cfg: StatsConfig, log: slog::Logger) {
+    let interval = Duration::from_secs(cfg.interval_minutes.max(1) * 60);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let counters = match counters.upgrade() {
+            Some(c) => c,
+            None => return,
+        };
+
+        match counters.lock() {
+            Ok(mut c) => {
+                info!(log, "{}", c.summary());
+                if cfg.reset {
+                    c.reset();
+                }
+            }
+            Err(_) => {
+                error!(log, "failed to obtain event counters lock");
+                return;
+            }
+        };
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let mut counters = EventCounters::default();
+        assert_eq!(counters.summary(), "events: none");
+
+        for kind in &["push", "issue", "push", "pipeline", "push"] {
+            counters.increment(kind);
+        }
+        assert_eq!(counters.summary(), "events: issue=1 pipeline=1 push=3");
+
+        counters.reset();
+        assert_eq!(counters.summary(), "events: none");
+
+        counters.increment("emoji");
+        assert_eq!(
+            counters.prometheus("unknown_events_total"),
+            "# TYPE unknown_events_total counter\nunknown_events_total{kind=\"emoji\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn delivery_counters() {
+        let mut counters = DeliveryCounters::default();
+        counters.record("#ok", true);
+        counters.record("#ok", true);
+        counters.record("#broken", false);
+
+        let mut other = DeliveryCounters::default();
+        other.record("#ok", true);
+        counters.merge(other);
+
+        assert_eq!(
+            counters.prometheus(),
+            "# TYPE irc_messages_sent_total counter\n\
+             irc_messages_sent_total{channel=\"#ok\"} 3\n\
+             # TYPE irc_send_failures_total counter\n\
+             irc_send_failures_total{channel=\"#broken\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn label_escaping() {
+        let counts = vec![(String::from("a\"b\\c"), 1)].into_iter().collect();
+        assert_eq!(
+            prometheus_counter("x_total", "channel", &counts),
+            "# TYPE x_total counter\nx_total{channel=\"a\\\"b\\\\c\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn request_times() {
+        let mut times = RequestTimes::default();
+        times.observe(Duration::from_millis(5));
+        times.observe(Duration::from_millis(300));
+        times.observe(Duration::from_secs(20));
+
+        assert_eq!(
+            times.prometheus("t_seconds"),
+            "# TYPE t_seconds histogram\n\
+             t_seconds_bucket{le=\"0.01\"} 1\n\
+             t_seconds_bucket{le=\"0.05\"} 1\n\
+             t_seconds_bucket{le=\"0.1\"} 1\n\
+             t_seconds_bucket{le=\"0.25\"} 1\n\
+             t_seconds_bucket{le=\"0.5\"} 2\n\
+             t_seconds_bucket{le=\"1\"} 2\n\
+             t_seconds_bucket{le=\"2.5\"} 2\n\
+             t_seconds_bucket{le=\"5\"} 2\n\
+             t_seconds_bucket{le=\"10\"} 2\n\
+             t_seconds_bucket{le=\"+Inf\"} 3\n\
+             t_seconds_sum 20.305\n\
+             t_seconds_count 3\n"
+        );
+    }
+}
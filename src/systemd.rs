@@ -0,0 +1,67 @@
+use std::{env, io, os::unix::net::UnixDatagram, thread, time::Duration};
+
+/// Sends `state` to systemd if it asked to be told, see sd_notify(3).
+/// Returns whether anything was sent.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+
+    if path.to_string_lossy().starts_with('@') {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "abstract notification sockets are not supported",
+        ));
+    }
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(true)
+}
+
+/// Half of the watchdog interval systemd expects pings in, if it expects any.
+fn watchdog_interval() -> Option<Duration> {
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(|usec| Duration::from_micros(usec / 2))
+}
+
+/// Tells systemd that startup is done and keeps the watchdog happy from then on.
+pub fn ready(log: &slog::Logger) {
+    match notify("READY=1") {
+        Ok(true) => info!(log, "notified systemd that raccoon is ready"),
+        Ok(false) => return,
+        Err(e) => {
+            warn!(log, "failed to notify systemd: {}", e);
+            return;
+        }
+    }
+
+    if let Some(interval) = watchdog_interval() {
+        let log = log.new(o!());
+        thread::spawn(move || loop {
+            if let Err(e) = notify("WATCHDOG=1") {
+                warn!(log, "failed to ping the systemd watchdog: {}", e);
+            }
+            thread::sleep(interval);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_under_systemd() {
+        env::remove_var("NOTIFY_SOCKET");
+        env::remove_var("WATCHDOG_USEC");
+
+        assert!(!notify("READY=1").unwrap());
+        assert_eq!(watchdog_interval(), None);
+        ready(&slog::Logger::root(slog::Discard, o!()));
+    }
+}
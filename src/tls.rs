@@ -0,0 +1,65 @@
+use gotham::rustls::{internal::pemfile, NoClientAuth, PrivateKey, ServerConfig};
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::error::Error;
+
+fn reader(path: &Path) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::Config(format!("failed to open {}: {}", path.display(), e)))
+}
+
+/// The private key in the PEM file at `path`, either PKCS#8 or RSA.
+fn private_key(path: &Path) -> Result<PrivateKey, Error> {
+    let invalid = |_| Error::Config(format!("invalid private key in {}", path.display()));
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader(path)?).map_err(invalid)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut reader(path)?).map_err(invalid)?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| Error::Config(format!("no private key in {}", path.display())))
+}
+
+/// Settings for serving HTTPS with the certificate chain at `cert`
+/// and its private key at `key`, both PEM files.
+pub fn server_config(cert: &Path, key: &Path) -> Result<ServerConfig, Error> {
+    let certs = pemfile::certs(&mut reader(cert)?)
+        .map_err(|_| Error::Config(format!("invalid certificate in {}", cert.display())))?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!(
+            "no certificate in {}",
+            cert.display()
+        )));
+    }
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, private_key(key)?)
+        .map_err(|e| Error::Config(format!("invalid TLS certificate or key: {}", e)))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_and_key() {
+        assert!(server_config(
+            Path::new("test/tls/cert.pem"),
+            Path::new("test/tls/key.pem")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn invalid_files() {
+        let cert = Path::new("test/tls/cert.pem");
+        let key = Path::new("test/tls/key.pem");
+        assert!(server_config(Path::new("test/tls/missing.pem"), key).is_err());
+        assert!(server_config(key, key).is_err());
+        assert!(server_config(cert, cert).is_err());
+        assert!(server_config(cert, Path::new("test/push.json")).is_err());
+    }
+}
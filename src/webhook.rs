@@ -0,0 +1,321 @@
+use futures::{future::Future, stream::Stream};
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Body, Client, HeaderMap, Request, Uri,
+};
+use hyper_rustls::HttpsConnector;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc},
+    time::{Duration, SystemTime},
+};
+use tokio::{runtime::Runtime, timer::Timeout};
+
+use crate::clock::Clock;
+use crate::error::Error;
+use crate::irc::{IrcWriter, Status};
+use crate::notification::Notification;
+use crate::secret;
+
+/// Settings of the `webhook` backend, read from the `webhook` table.
+#[derive(Deserialize, Debug, Clone)]
+struct WebhookConfig {
+    url: String,
+    /// Extra headers to send, e.g. for authentication.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+/// POSTs every notification as JSON to a URL, for integrations other than IRC.
+pub struct WebhookWriter {
+    url: Uri,
+    headers: HeaderMap,
+    timeout: Duration,
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// Handlers already run on a runtime, so requests go on one of their own.
+    runtime: Runtime,
+    clock: Arc<Clock + Send + Sync>,
+    logger: slog::Logger,
+}
+
+impl WebhookWriter {
+    /// Creates the writer configured under `webhook`.
+    pub fn from_config(
+        cfg: &config::Config,
+        clock: Arc<Clock + Send + Sync>,
+        logger: &slog::Logger,
+    ) -> Result<Self, Error> {
+        let webhook = cfg.get::<WebhookConfig>("webhook").map_err(|e| match e {
+            config::ConfigError::NotFound(_) => {
+                Error::Config(String::from("the webhook backend needs webhook.url"))
+            }
+            // the errors of the config crate may quote the headers
+            e => Error::Config(secret::redact(
+                &format!("failed to parse webhook config: {}", e),
+                cfg,
+            )),
+        })?;
+
+        let url = webhook
+            .url
+            .parse::<Uri>()
+            .map_err(|e| Error::Config(format!("invalid webhook url: {}", e)))?;
+        match url.scheme_str() {
+            Some("http") | Some("https") => (),
+            _ => {
+                return Err(Error::Config(String::from(
+                    "webhook url must be an http or https URL",
+                )))
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &webhook.headers {
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::Config(format!("invalid webhook header name {}", name)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| Error::Config(format!("invalid value for webhook header {}", name)))?;
+            headers.insert(header, value);
+        }
+
+        let runtime = tokio::runtime::Builder::new()
+            .core_threads(1)
+            .name_prefix("webhook-")
+            .build()
+            .map_err(|e| Error::Backend(format!("failed to start webhook runtime: {}", e)))?;
+
+        Ok(WebhookWriter {
+            url,
+            headers,
+            timeout: Duration::from_millis(webhook.timeout_ms),
+            client: Client::builder().build(HttpsConnector::new(1)),
+            runtime,
+            clock,
+            logger: logger.clone(),
+        })
+    }
+
+    fn post(&self, body: String) -> Result<(), Error> {
+        let mut req = Request::post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .map_err(|e| Error::Backend(format!("failed to build webhook request: {}", e)))?;
+        for (name, value) in self.headers.iter() {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+
+        let request = self.client.request(req).and_then(|res| {
+            let status = res.status();
+            res.into_body().concat2().map(move |b| (status, b))
+        });
+
+        // a request that takes too long is dropped, closing its connection
+        let (tx, rx) = mpsc::channel();
+        self.runtime
+            .executor()
+            .spawn(Timeout::new(request, self.timeout).then(move |res| {
+                let _ = tx.send(res);
+                Ok(())
+            }));
+
+        let (status, body) = rx
+            .recv()
+            .map_err(|_| Error::Backend(String::from("webhook request was dropped")))?
+            .map_err(|e| {
+                if e.is_elapsed() {
+                    Error::Backend(String::from("webhook timed out"))
+                } else {
+                    Error::Backend(format!("webhook failed: {}", e))
+                }
+            })?;
+        if !status.is_success() {
+            warn!(
+                self.logger,
+                "webhook answered {}: {}",
+                status,
+                String::from_utf8_lossy(&body).trim()
+            );
+            return Err(Error::Backend(format!("webhook answered {}", status)));
+        }
+
+        Ok(())
+    }
+}
+
+/// The JSON body for a notification of `kind` sent at `time`.
+fn payload(kind: &str, project: Option<&String>, message: &str, time: SystemTime) -> String {
+    json!({
+        "kind": kind,
+        "project": project,
+        "message": message,
+        "timestamp": chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339(),
+    })
+    .to_string()
+}
+
+impl IrcWriter for WebhookWriter {
+    fn write(&mut self, message: &str) -> Result<(), Error> {
+        self.post(payload("message", None, message, self.clock.system_time()))
+    }
+
+    fn notify(&mut self, notification: &Notification) -> Result<(), Error> {
+        self.post(payload(
+            &notification.kind,
+            notification.project.as_ref(),
+            &notification.message,
+            self.clock.system_time(),
+        ))
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            connected: true,
+            channels: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    /// Answers one request with `status` and hands back its headers and body.
+    fn mock_server(status: &'static str) -> (String, mpsc::Receiver<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_owned();
+                if line.is_empty() {
+                    break;
+                }
+                headers.push(line.to_lowercase());
+            }
+            let length = headers
+                .iter()
+                .filter_map(|h| h.splitn(2, "content-length:").nth(1))
+                .filter_map(|l| l.trim().parse::<usize>().ok())
+                .next()
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status).unwrap();
+            tx.send((headers, String::from_utf8(body).unwrap()))
+                .unwrap();
+        });
+        (url, rx)
+    }
+
+    fn writer(url: &str) -> WebhookWriter {
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1_500_000_000));
+        let mut cfg = config::Config::default();
+        cfg.set("webhook.url", url).unwrap();
+        cfg.set("webhook.headers.X-Api-Key", "sekrit").unwrap();
+        cfg.set("webhook.timeout_ms", 2000).unwrap();
+        WebhookWriter::from_config(
+            &cfg,
+            Arc::new(clock),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn posts_json() {
+        let (url, rx) = mock_server("200 OK");
+        writer(&url)
+            .notify(&Notification {
+                kind: String::from("pipeline"),
+                project: Some(String::from("group/app")),
+                message: String::from("pipeline failed"),
+                ..Notification::default()
+            })
+            .unwrap();
+
+        let (headers, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(headers[0].starts_with("post /hook "));
+        assert!(headers.contains(&String::from("content-type: application/json")));
+        assert!(headers.contains(&String::from("x-api-key: sekrit")));
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["kind"], "pipeline");
+        assert_eq!(body["project"], "group/app");
+        assert_eq!(body["message"], "pipeline failed");
+        assert_eq!(body["timestamp"], "2017-07-14T02:40:00+00:00");
+    }
+
+    #[test]
+    fn error_status() {
+        let (url, rx) = mock_server("500 Internal Server Error");
+        assert!(writer(&url).write("hello").is_err());
+
+        let (_, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["kind"], "message");
+        assert!(body["project"].is_null());
+    }
+
+    #[test]
+    fn times_out() {
+        // accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = rx.recv();
+            drop(stream);
+        });
+
+        let mut cfg = config::Config::default();
+        cfg.set("webhook.url", url).unwrap();
+        cfg.set("webhook.timeout_ms", 100).unwrap();
+        let mut writer = WebhookWriter::from_config(
+            &cfg,
+            Arc::new(SystemClock),
+            &slog::Logger::root(slog::Discard, o!()),
+        )
+        .unwrap();
+        match writer.write("hello") {
+            Err(Error::Backend(e)) => assert_eq!(e, "webhook timed out"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        let _ = tx.send(());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let cfg = config::Config::default();
+        assert!(WebhookWriter::from_config(&cfg, Arc::new(SystemClock), &log).is_err());
+
+        let mut cfg = config::Config::default();
+        cfg.set("webhook.url", "https://example.com/hook").unwrap();
+        assert!(WebhookWriter::from_config(&cfg, Arc::new(SystemClock), &log).is_ok());
+
+        cfg.set("webhook.url", "ftp://example.com/hook").unwrap();
+        assert!(WebhookWriter::from_config(&cfg, Arc::new(SystemClock), &log).is_err());
+    }
+}